@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use similar::TextDiff;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::pack::{self, PackEntry};
+
+/// Options for writing an edited pack back onto a working tree.
+pub struct ApplyOptions<'a> {
+    pub root: &'a str,
+    pub dry_run: bool,
+    pub create: bool,
+    pub delete_missing: Option<&'a str>,
+}
+
+/// Applies the file sections of an edited `--format pack` output to the files
+/// under `options.root`, printing a unified diff of every change.
+pub fn apply(edited_file: &str, options: &ApplyOptions) -> Result<()> {
+    let entries = read_pack(edited_file)?;
+    let root = Path::new(options.root);
+
+    let (mut modified, mut created, mut deleted, mut unchanged, mut skipped) = (0, 0, 0, 0, 0);
+    for entry in &entries {
+        let target = pack::safe_join(root, &entry.path)?;
+        let current = if target.exists() {
+            Some(
+                fs::read_to_string(&target)
+                    .with_context(|| format!("Failed to read '{}'", target.display()))?,
+            )
+        } else {
+            None
+        };
+
+        match current {
+            Some(current) if current == entry.contents => unchanged += 1,
+            Some(current) => {
+                print_diff(&entry.path, Some(&current), Some(&entry.contents));
+                if !options.dry_run {
+                    fs::write(&target, &entry.contents)
+                        .with_context(|| format!("Failed to write '{}'", target.display()))?;
+                }
+                modified += 1;
+            }
+            None if options.create => {
+                print_diff(&entry.path, None, Some(&entry.contents));
+                if !options.dry_run {
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)
+                            .with_context(|| format!("Failed to create '{}'", parent.display()))?;
+                    }
+                    fs::write(&target, &entry.contents)
+                        .with_context(|| format!("Failed to write '{}'", target.display()))?;
+                }
+                created += 1;
+            }
+            None => {
+                println!(
+                    "Skipping new file '{}' (pass --create to write it)",
+                    entry.path
+                );
+                skipped += 1;
+            }
+        }
+    }
+
+    if let Some(original_file) = options.delete_missing {
+        let kept: HashSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        for entry in read_pack(original_file)? {
+            if kept.contains(entry.path.as_str()) {
+                continue;
+            }
+            let target = pack::safe_join(root, &entry.path)?;
+            if !target.is_file() {
+                continue;
+            }
+            let current = fs::read_to_string(&target)
+                .with_context(|| format!("Failed to read '{}'", target.display()))?;
+            print_diff(&entry.path, Some(&current), None);
+            if !options.dry_run {
+                fs::remove_file(&target)
+                    .with_context(|| format!("Failed to delete '{}'", target.display()))?;
+            }
+            deleted += 1;
+        }
+    }
+
+    println!(
+        "{}{} modified, {} created, {} deleted, {} unchanged, {} skipped",
+        if options.dry_run { "Dry run: " } else { "" },
+        modified,
+        created,
+        deleted,
+        unchanged,
+        skipped
+    );
+    Ok(())
+}
+
+fn read_pack(file: &str) -> Result<Vec<PackEntry>> {
+    let data =
+        fs::read_to_string(file).with_context(|| format!("Failed to read pack '{}'", file))?;
+    pack::parse(&data).with_context(|| format!("Failed to parse pack '{}'", file))
+}
+
+fn print_diff(path: &str, old: Option<&str>, new: Option<&str>) {
+    let old_header = old.map_or("/dev/null".to_string(), |_| format!("a/{}", path));
+    let new_header = new.map_or("/dev/null".to_string(), |_| format!("b/{}", path));
+    let diff = TextDiff::from_lines(old.unwrap_or(""), new.unwrap_or(""));
+    print!(
+        "{}",
+        diff.unified_diff()
+            .context_radius(3)
+            .header(&old_header, &new_header)
+    );
+}
@@ -0,0 +1,418 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek};
+use std::path::Path;
+
+use crate::{pack, relative_path, text, FileRecord, Options};
+
+/// How far reading an archive may go before it is refused.
+struct Limits {
+    /// Most entries read from an archive, nested ones included
+    entries: usize,
+    /// Most bytes read out of the entries of an archive, oversized ones
+    /// included
+    total_size: u64,
+    /// Largest single file loaded; bigger ones are skipped
+    file_size: u64,
+    /// Largest nested archive loaded into memory
+    nested_size: u64,
+    /// Highest compression ratio believed for large zip entries
+    zip_ratio: u64,
+}
+
+const LIMITS: Limits = Limits {
+    entries: 100_000,
+    total_size: 4 << 30,
+    file_size: 32 << 20,
+    nested_size: 512 << 20,
+    zip_ratio: 1000,
+};
+
+/// Archive formats accepted as `--input`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+    TarXz,
+    TarZst,
+}
+
+impl Kind {
+    const EXTENSIONS: &'static [(&'static str, Kind)] = &[
+        (".zip", Kind::Zip),
+        (".tar", Kind::Tar),
+        (".tar.gz", Kind::TarGz),
+        (".tgz", Kind::TarGz),
+        (".tar.xz", Kind::TarXz),
+        (".txz", Kind::TarXz),
+        (".tar.zst", Kind::TarZst),
+        (".tzst", Kind::TarZst),
+    ];
+
+    /// Recognizes an archive by its file name.
+    pub fn detect(name: &str) -> Option<Kind> {
+        Self::split(name).map(|(_, kind)| kind)
+    }
+
+    /// `name` without its archive extension, and the kind of archive.
+    pub fn split(name: &str) -> Option<(&str, Kind)> {
+        let lower = name.to_ascii_lowercase();
+        Self::EXTENSIONS
+            .iter()
+            .filter(|(extension, _)| lower.ends_with(extension))
+            .max_by_key(|(extension, _)| extension.len())
+            .map(|(extension, kind)| (&name[..name.len() - extension.len()], *kind))
+    }
+}
+
+/// Files and directories read from an archive.
+pub struct Contents {
+    pub records: Vec<FileRecord>,
+    pub dirs: Vec<String>,
+}
+
+/// Reads the files in the archive at `path` that the include and exclude
+/// patterns select, straight from the compressed stream and without
+/// writing anything to disk. Archives inside it are read too, one level
+/// deep, with their files placed under the archive's name minus its
+/// extension. Entries with absolute or `..` paths are skipped, and reading
+/// stops once the archive expands suspiciously far.
+pub fn read(path: &str, options: &Options) -> Result<Contents> {
+    let kind = Kind::detect(path).with_context(|| format!("'{}' is not an archive", path))?;
+    let file = File::open(path).with_context(|| format!("Failed to open '{}'", path))?;
+    let selects = |rel_path: &str| options.selects(rel_path);
+    let mut reader = ArchiveReader::new(Path::new(path), &selects);
+    reader
+        .visit(kind, file, "", false)
+        .with_context(|| format!("Failed to read archive '{}'", path))?;
+    Ok(reader.contents())
+}
+
+struct ArchiveReader<'a> {
+    archive: &'a Path,
+    /// Whether the file at a path inside the archive is part of the output
+    selects: &'a dyn Fn(&str) -> bool,
+    limits: Limits,
+    records: Vec<FileRecord>,
+    dirs: BTreeSet<String>,
+    entries: usize,
+    total_size: u64,
+}
+
+impl<'a> ArchiveReader<'a> {
+    fn new(archive: &'a Path, selects: &'a dyn Fn(&str) -> bool) -> Self {
+        ArchiveReader {
+            archive,
+            selects,
+            limits: LIMITS,
+            records: Vec::new(),
+            dirs: BTreeSet::new(),
+            entries: 0,
+            total_size: 0,
+        }
+    }
+
+    fn contents(self) -> Contents {
+        Contents {
+            records: self.records,
+            dirs: self.dirs.into_iter().collect(),
+        }
+    }
+
+    fn visit<R: Read + Seek>(
+        &mut self,
+        kind: Kind,
+        reader: R,
+        prefix: &str,
+        nested: bool,
+    ) -> Result<()> {
+        match kind {
+            Kind::Zip => self.visit_zip(reader, prefix, nested),
+            Kind::Tar => self.visit_tar(reader, prefix, nested),
+            Kind::TarGz => self.visit_tar(flate2::read::GzDecoder::new(reader), prefix, nested),
+            Kind::TarXz => self.visit_tar(
+                lzma_rust2::XzReader::new(BufReader::new(reader), true),
+                prefix,
+                nested,
+            ),
+            Kind::TarZst => self.visit_tar(
+                ruzstd::decoding::StreamingDecoder::new(BufReader::new(reader))
+                    .context("Invalid zstd stream")?,
+                prefix,
+                nested,
+            ),
+        }
+    }
+
+    fn visit_tar<R: Read>(&mut self, reader: R, prefix: &str, nested: bool) -> Result<()> {
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            // Links could point anywhere, so only regular files are read.
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+            self.entry(&name, &mut entry, prefix, nested)?;
+        }
+        Ok(())
+    }
+
+    fn visit_zip<R: Read + Seek>(&mut self, reader: R, prefix: &str, nested: bool) -> Result<()> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if !entry.is_file() {
+                continue;
+            }
+            let name = entry.name().to_string();
+            let (size, compressed) = (entry.size(), entry.compressed_size());
+            if size > 1 << 20 && size / compressed.max(1) > self.limits.zip_ratio {
+                bail!(
+                    "Refusing '{}': it claims to expand {} times, which looks like a zip bomb",
+                    name,
+                    size / compressed.max(1)
+                );
+            }
+            self.entry(&name, &mut entry, prefix, nested)?;
+        }
+        Ok(())
+    }
+
+    fn entry(
+        &mut self,
+        name: &str,
+        reader: &mut dyn Read,
+        prefix: &str,
+        nested: bool,
+    ) -> Result<()> {
+        self.entries += 1;
+        if self.entries > self.limits.entries {
+            bail!("Refusing to read more than {} entries", self.limits.entries);
+        }
+
+        let sanitized = match pack::sanitize_path(name) {
+            Ok(path) => relative_path(&path, Path::new("")),
+            Err(error) => {
+                println!("Skipping entry: {:#}", error);
+                return Ok(());
+            }
+        };
+        let rel_path = if prefix.is_empty() {
+            sanitized
+        } else {
+            format!("{}/{}", prefix, sanitized)
+        };
+        // Hidden entries are skipped, like the folder walker does.
+        if rel_path.split('/').any(|part| part.starts_with('.')) {
+            return Ok(());
+        }
+        let mut parent = rel_path.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            self.dirs.insert(dir.to_string());
+            parent = dir;
+        }
+
+        if let Some((stem, kind)) = Kind::split(&rel_path) {
+            if nested {
+                println!(
+                    "Skipping {}: archives are only read one level deep",
+                    rel_path
+                );
+                return Ok(());
+            }
+            let Some(bytes) = self.read_limited(reader, self.limits.nested_size)? else {
+                println!(
+                    "Skipping {}: larger than {} bytes",
+                    rel_path, self.limits.nested_size
+                );
+                return Ok(());
+            };
+            self.dirs.insert(stem.to_string());
+            return self
+                .visit(kind, Cursor::new(bytes), stem, true)
+                .with_context(|| format!("Failed to read nested archive '{}'", rel_path));
+        }
+
+        let path = self.archive.join(&rel_path);
+        if !(self.selects)(&rel_path) {
+            return Ok(());
+        }
+        let Some(bytes) = self.read_limited(reader, self.limits.file_size)? else {
+            println!(
+                "Skipping {}: larger than {} bytes",
+                rel_path, self.limits.file_size
+            );
+            return Ok(());
+        };
+        let Some(contents) = text(bytes, &rel_path) else {
+            return Ok(());
+        };
+        println!("{}", path.display());
+        self.records.push(FileRecord {
+            path,
+            rel_path,
+            contents,
+            truncated: false,
+            diff: None,
+            last_commit: None,
+        });
+        Ok(())
+    }
+
+    /// Reads all of `reader` unless it holds more than `limit` bytes, whatever
+    /// size the archive claimed. The bytes actually decompressed count
+    /// towards the total size limit, so headers understating them do not
+    /// help.
+    fn read_limited(&mut self, reader: &mut dyn Read, limit: u64) -> Result<Option<Vec<u8>>> {
+        let mut bytes = Vec::new();
+        reader.take(limit + 1).read_to_end(&mut bytes)?;
+        self.total_size += bytes.len() as u64;
+        if self.total_size > self.limits.total_size {
+            bail!(
+                "Refusing to expand to more than {} bytes",
+                self.limits.total_size
+            );
+        }
+        Ok((bytes.len() as u64 <= limit).then_some(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A tar archive of regular files. Names are written into the header
+    /// as is, since `tar::Builder` itself refuses unsafe ones.
+    fn tar(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn read_with(kind: Kind, bytes: Vec<u8>, limits: Limits) -> Result<Contents> {
+        let selects = |_: &str| true;
+        let mut reader = ArchiveReader::new(Path::new("drop"), &selects);
+        reader.limits = limits;
+        reader.visit(kind, Cursor::new(bytes), "", false)?;
+        Ok(reader.contents())
+    }
+
+    fn paths(kind: Kind, bytes: Vec<u8>) -> Vec<String> {
+        read_with(kind, bytes, LIMITS)
+            .unwrap()
+            .records
+            .into_iter()
+            .map(|record| record.rel_path)
+            .collect()
+    }
+
+    #[test]
+    fn skips_absolute_and_parent_paths() {
+        let entries: &[(&str, &[u8])] = &[
+            ("../escape.txt", b"no"),
+            ("src/../../escape.txt", b"no"),
+            ("/etc/passwd", b"no"),
+            ("src/lib.rs", b"yes"),
+        ];
+        assert_eq!(paths(Kind::Tar, tar(entries)), ["src/lib.rs"]);
+        assert_eq!(paths(Kind::Zip, zip(entries)), ["src/lib.rs"]);
+    }
+
+    #[test]
+    fn skips_hidden_entries() {
+        let entries: &[(&str, &[u8])] = &[
+            (".env", b"SECRET=1"),
+            (".git/config", b"[core]"),
+            ("src/.cache/data.txt", b"cached"),
+            ("src/main.rs", b"fn main() {}"),
+        ];
+        assert_eq!(paths(Kind::Tar, tar(entries)), ["src/main.rs"]);
+    }
+
+    #[test]
+    fn reads_nested_archives_one_level_deep() {
+        let innermost = tar(&[("deep.txt", b"too deep")]);
+        let inner = zip(&[("a.txt", b"nested"), ("more.tar", &innermost)]);
+        let outer = tar(&[("README.md", b"top"), ("vendor/lib.zip", &inner)]);
+        let contents = read_with(Kind::Tar, outer, LIMITS).unwrap();
+        let paths: Vec<&str> = contents
+            .records
+            .iter()
+            .map(|record| record.rel_path.as_str())
+            .collect();
+        assert_eq!(paths, ["README.md", "vendor/lib/a.txt"]);
+        assert_eq!(contents.records[1].contents, "nested");
+        assert!(contents.dirs.contains(&"vendor/lib".to_string()));
+    }
+
+    #[test]
+    fn skips_files_over_the_size_limit() {
+        let archive = tar(&[("big.txt", &[b'x'; 64]), ("small.txt", b"ok")]);
+        let limits = Limits {
+            file_size: 16,
+            ..LIMITS
+        };
+        let contents = read_with(Kind::Tar, archive, limits).unwrap();
+        assert_eq!(contents.records.len(), 1);
+        assert_eq!(contents.records[0].rel_path, "small.txt");
+    }
+
+    #[test]
+    fn refuses_to_expand_past_the_total_limit() {
+        let archive = tar(&[("a.txt", &[b'x'; 64]), ("b.txt", &[b'x'; 64])]);
+        let limits = Limits {
+            total_size: 100,
+            ..LIMITS
+        };
+        let error = read_with(Kind::Tar, archive, limits).err().unwrap();
+        assert!(
+            error.to_string().contains("more than 100 bytes"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn refuses_too_many_entries() {
+        let archive = tar(&[("a.txt", b"a"), ("b.txt", b"b"), ("c.txt", b"c")]);
+        let limits = Limits {
+            entries: 2,
+            ..LIMITS
+        };
+        assert!(read_with(Kind::Tar, archive, limits).is_err());
+    }
+
+    #[test]
+    fn refuses_zip_bombs() {
+        let archive = zip(&[("zeros.txt", &vec![0; 4 << 20])]);
+        let limits = Limits {
+            zip_ratio: 100,
+            ..LIMITS
+        };
+        let error = read_with(Kind::Zip, archive, limits).err().unwrap();
+        assert!(error.to_string().contains("zip bomb"), "{}", error);
+    }
+}
@@ -0,0 +1,228 @@
+use anyhow::Result;
+
+use crate::filter::Priority;
+use crate::format::Renderer;
+use crate::tokens::TokenCounter;
+use crate::FileRecord;
+
+/// File names that usually explain a project or show where it starts.
+const ENTRY_POINTS: &[&str] = &[
+    "main.rs",
+    "lib.rs",
+    "Cargo.toml",
+    "__main__.py",
+    "main.py",
+    "app.py",
+    "setup.py",
+    "pyproject.toml",
+    "main.go",
+    "go.mod",
+    "main.c",
+    "main.cpp",
+    "main.cu",
+    "index.js",
+    "index.ts",
+    "package.json",
+];
+
+/// Fewest lines worth keeping from a file before omitting it entirely.
+const MIN_EXCERPT_LINES: usize = 8;
+
+/// How much of a file makes it into a budgeted output.
+enum Choice {
+    Whole,
+    Excerpt(FileRecord),
+    Note(FileRecord),
+    Dropped,
+}
+
+/// Picks files in priority order until `max_tokens` is used up.
+///
+/// Room for a short omission note is set aside for every file first, so no
+/// file disappears without a trace. Files are then kept whole in priority
+/// order as long as they fit, and the budget left over goes to head/tail
+/// excerpts of the others; the rest keep their note. Notes are only dropped,
+/// lowest priority first, when even they do not all fit. Packs must stay
+/// lossless, so with `--format pack` files are either kept whole or dropped.
+pub fn select(
+    records: Vec<FileRecord>,
+    max_tokens: usize,
+    priority: &Priority,
+    renderer: &Renderer,
+    counter: &TokenCounter,
+) -> Result<Vec<FileRecord>> {
+    let mut records = records;
+    records.sort_by_cached_key(|record| rank(record, priority));
+
+    let separator = counter.count(&renderer.separator());
+    // Files are costed at their position among all candidates, which is never
+    // before the one they end up at, so dropping others cannot make them grow.
+    let cost = |index: usize, record: &FileRecord| -> Result<usize> {
+        let separator = if index > 0 { separator } else { 0 };
+        Ok(counter.count(&renderer.render_file(index, record)?) + separator)
+    };
+    let mut used = counter.count(&renderer.header()) + counter.count(&renderer.footer());
+    let lossless = renderer.is_lossless();
+
+    let mut choices = Vec::new();
+    let mut costs = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        if lossless {
+            choices.push(Choice::Dropped);
+            costs.push(0);
+            continue;
+        }
+        let whole = cost(index, record)?;
+        let note = note(record, whole);
+        let tokens = cost(index, &note)?;
+        // Tiny files cost less whole than as a note.
+        if whole <= tokens {
+            choices.push(Choice::Whole);
+            costs.push(whole);
+            used += whole;
+        } else {
+            choices.push(Choice::Note(note));
+            costs.push(tokens);
+            used += tokens;
+        }
+    }
+    // Without room for every note, the lowest ranked ones go first.
+    for index in (0..records.len()).rev() {
+        if used <= max_tokens {
+            break;
+        }
+        if !matches!(choices[index], Choice::Dropped) {
+            used -= costs[index];
+            choices[index] = Choice::Dropped;
+            costs[index] = 0;
+        }
+    }
+
+    for (index, record) in records.iter().enumerate() {
+        if matches!(choices[index], Choice::Whole | Choice::Excerpt(_)) {
+            continue;
+        }
+        let tokens = cost(index, record)?;
+        let room = max_tokens.saturating_sub(used) + costs[index];
+        if tokens <= room {
+            used = used - costs[index] + tokens;
+            costs[index] = tokens;
+            choices[index] = Choice::Whole;
+        }
+    }
+
+    if !lossless {
+        for (index, record) in records.iter().enumerate() {
+            if !matches!(choices[index], Choice::Note(_)) {
+                continue;
+            }
+            let room = max_tokens.saturating_sub(used) + costs[index];
+            if let Some((excerpt, tokens)) =
+                fit_excerpt(record, room, &|excerpt| cost(index, excerpt))?
+            {
+                used = used - costs[index] + tokens;
+                costs[index] = tokens;
+                choices[index] = Choice::Excerpt(excerpt);
+            }
+        }
+    }
+
+    let mut selected = Vec::new();
+    for (record, choice) in records.into_iter().zip(choices) {
+        match choice {
+            Choice::Whole => selected.push(record),
+            Choice::Excerpt(excerpt) => {
+                println!("Truncated {} to fit the token budget", record.rel_path);
+                selected.push(excerpt);
+            }
+            Choice::Note(note) => {
+                println!("Omitted {} to fit the token budget", record.rel_path);
+                selected.push(note);
+            }
+            Choice::Dropped => println!("Dropped {} to fit the token budget", record.rel_path),
+        }
+    }
+    Ok(selected)
+}
+
+/// Short note standing in for a file of `tokens` tokens that did not fit.
+fn note(record: &FileRecord, tokens: usize) -> FileRecord {
+    with_contents(
+        record,
+        format!(
+            "[{} omitted by repocat: {} tokens did not fit the token budget]",
+            record.rel_path, tokens
+        ),
+    )
+}
+
+/// Sort key: READMEs, then entry points, then `--priority` patterns in the
+/// order given, then everything else; shallower paths first within a tier.
+fn rank(record: &FileRecord, priority: &Priority) -> (usize, usize, String) {
+    let name = record
+        .rel_path
+        .rsplit('/')
+        .next()
+        .unwrap_or(&record.rel_path);
+    let tier = if name.to_ascii_lowercase().starts_with("readme") {
+        0
+    } else if ENTRY_POINTS.contains(&name) {
+        1
+    } else {
+        priority
+            .rank(&record.rel_path)
+            .map_or(2 + priority.len(), |position| 2 + position)
+    };
+    let depth = record.rel_path.matches('/').count();
+    (tier, depth, record.rel_path.clone())
+}
+
+/// Largest head/tail excerpt of `record` whose cost fits in `remaining`.
+fn fit_excerpt(
+    record: &FileRecord,
+    remaining: usize,
+    cost: &dyn Fn(&FileRecord) -> Result<usize>,
+) -> Result<Option<(FileRecord, usize)>> {
+    let lines: Vec<&str> = record.contents.lines().collect();
+    if lines.len() <= MIN_EXCERPT_LINES {
+        return Ok(None);
+    }
+
+    let mut best = None;
+    let (mut low, mut high) = (MIN_EXCERPT_LINES, lines.len() - 1);
+    while low <= high {
+        let keep = (low + high) / 2;
+        let excerpt = excerpt(record, &lines, keep);
+        let tokens = cost(&excerpt)?;
+        if tokens <= remaining {
+            best = Some((excerpt, tokens));
+            low = keep + 1;
+        } else {
+            high = keep - 1;
+        }
+    }
+    Ok(best)
+}
+
+fn excerpt(record: &FileRecord, lines: &[&str], keep: usize) -> FileRecord {
+    let head = keep.div_ceil(2);
+    let tail = keep - head;
+    let contents = format!(
+        "{}\n... [{} lines omitted by repocat to fit the token budget] ...\n{}\n",
+        lines[..head].join("\n"),
+        lines.len() - keep,
+        lines[lines.len() - tail..].join("\n")
+    );
+    with_contents(record, contents)
+}
+
+fn with_contents(record: &FileRecord, contents: String) -> FileRecord {
+    FileRecord {
+        path: record.path.clone(),
+        rel_path: record.rel_path.clone(),
+        contents,
+        truncated: true,
+        diff: record.diff.clone(),
+        last_commit: record.last_commit.clone(),
+    }
+}
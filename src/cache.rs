@@ -0,0 +1,273 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::auth;
+use crate::remote::{self, git, redact_url, RemoteSpec, BASE_REF};
+use crate::tree::human_size;
+
+/// File inside a mirror whose modification time records when it was last
+/// used.
+const LAST_USED: &str = "repocat-last-used";
+
+/// Refs kept in a mirror. Others, such as GitHub's `refs/pull/*`, can be
+/// numerous and are only fetched when asked for.
+const REFSPECS: &[&str] = &["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where mirrors are kept: `$XDG_CACHE_HOME/repocat`, or `~/.cache/repocat`.
+pub fn dir() -> Result<PathBuf> {
+    if let Some(cache) = env::var_os("XDG_CACHE_HOME").filter(|cache| !cache.is_empty()) {
+        return Ok(PathBuf::from(cache).join("repocat"));
+    }
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .context("Cannot find a cache directory: neither XDG_CACHE_HOME nor HOME is set")?;
+    Ok(PathBuf::from(home).join(".cache").join("repocat"))
+}
+
+/// Mirror of `url`, named after the repository plus a hash of the full URL.
+fn mirror_path(url: &str) -> Result<PathBuf> {
+    let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+    let name: String = remote::repo_name(url)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Ok(dir()?.join(format!("{}-{}.git", name, &hash[..12])))
+}
+
+/// Checks `spec` out into the empty directory `dest` from the cached mirror
+/// of its repository, and returns the commit checked out.
+///
+/// The mirror is a blobless bare clone of the branches and tags, created on
+/// first use and updated with an incremental fetch on every later one (or
+/// created anew with `refresh`). Any other ref is fetched when asked for.
+/// Checkouts are worktrees of the mirror, so file contents downloaded for
+/// one run are kept for the next.
+pub fn checkout(spec: &RemoteSpec, dest: &Path, refresh: bool) -> Result<String> {
+    let mirror = mirror_path(&spec.url)?;
+    if refresh && mirror.exists() {
+        fs::remove_dir_all(&mirror)?;
+    }
+    if mirror.exists() {
+        println!("Updating cached mirror '{}'", mirror.display());
+        // Mirrors of older versions fetched every ref.
+        set_refspecs(&mirror)?;
+        if let Err(error) = git(&mirror, &["fetch", "--prune", "--quiet", "origin"]) {
+            println!("Could not update the mirror, using it as is: {:#}", error);
+        }
+    } else {
+        println!("Caching repository in '{}'", mirror.display());
+        create(&mirror, spec)?;
+    }
+    fs::write(mirror.join(LAST_USED), "")?;
+
+    let verify = |rev: &str| {
+        git(
+            &mirror,
+            &[
+                "rev-parse",
+                "--verify",
+                "--quiet",
+                &format!("{}^{{commit}}", rev),
+            ],
+        )
+    };
+    let resolve = |rev: &str| {
+        verify(rev)
+            .or_else(|_| {
+                // Commits and refs outside the branches and tags, such as
+                // `refs/pull/1/head`, are fetched on demand.
+                git(&mirror, &["fetch", "--quiet", "origin", rev])?;
+                verify("FETCH_HEAD")
+            })
+            .with_context(|| format!("Could not find '{}' in the repository", rev))
+    };
+    let commit = resolve(spec.reference.as_deref().unwrap_or("HEAD"))?;
+
+    // Forget worktrees of earlier runs, whose directories are long gone.
+    git(&mirror, &["worktree", "prune"])?;
+    let dest_arg = dest.to_string_lossy();
+    git(
+        &mirror,
+        &[
+            "worktree",
+            "add",
+            "--detach",
+            "--no-checkout",
+            &dest_arg,
+            &commit,
+        ],
+    )?;
+    if let Some(subdir) = &spec.subdir {
+        git(
+            dest,
+            &[
+                "sparse-checkout",
+                "set",
+                "--no-cone",
+                &format!("/{}", subdir),
+            ],
+        )?;
+    }
+    git(dest, &["reset", "--quiet", "--hard"])?;
+    if let Some(base) = &spec.base {
+        git(dest, &["update-ref", BASE_REF, &resolve(base)?])?;
+    }
+    Ok(commit)
+}
+
+fn create(mirror: &Path, spec: &RemoteSpec) -> Result<()> {
+    let parent = mirror
+        .parent()
+        .expect("mirrors live in the cache directory");
+    fs::create_dir_all(parent)?;
+    let token = auth::token_for(&spec.url);
+    // Only the name of the token's variable ends up in the arguments.
+    let helper = token
+        .as_ref()
+        .map(|token| format!("credential.helper={}", token.credential_helper()));
+    let mut clone = Vec::new();
+    if let Some(helper) = &helper {
+        clone.extend(["-c", "credential.helper=", "-c", helper]);
+    }
+    let mirror_arg = mirror.to_string_lossy();
+    clone.extend([
+        "clone",
+        "--bare",
+        "--filter=blob:none",
+        "--quiet",
+        &spec.url,
+        &mirror_arg,
+    ]);
+
+    let created = git(parent, &clone)
+        .and_then(|_| set_refspecs(mirror))
+        .and_then(|_| match &token {
+            Some(token) => remote::store_credential_helper(mirror, token),
+            None => Ok(()),
+        });
+    if created.is_err() && mirror.exists() {
+        fs::remove_dir_all(mirror)?;
+    }
+    created
+}
+
+/// Makes later fetches into `mirror` update its branches and tags only.
+fn set_refspecs(mirror: &Path) -> Result<()> {
+    git(mirror, &["config", "--unset-all", "remote.origin.fetch"]).ok();
+    for refspec in REFSPECS {
+        git(mirror, &["config", "--add", "remote.origin.fetch", refspec])?;
+    }
+    Ok(())
+}
+
+/// Prints every cached repository with its size and last use.
+pub fn list() -> Result<()> {
+    let mirrors = mirrors()?;
+    if mirrors.is_empty() {
+        println!("No cached repositories in '{}'", dir()?.display());
+        return Ok(());
+    }
+    for mirror in mirrors {
+        println!(
+            "{}\t{}\t{}\t{}",
+            origin(&mirror),
+            human_size(disk_usage(&mirror)),
+            describe_age(last_used(&mirror)),
+            mirror.display()
+        );
+    }
+    Ok(())
+}
+
+/// Removes cached repositories, or only those unused for `older_than` days.
+pub fn prune(older_than: Option<u64>) -> Result<()> {
+    let mut removed = 0;
+    let mut freed = 0;
+    for mirror in mirrors()? {
+        let age = last_used(&mirror).as_secs();
+        if older_than.is_some_and(|days| age < days.saturating_mul(DAY.as_secs())) {
+            continue;
+        }
+        let (origin, size) = (origin(&mirror), disk_usage(&mirror));
+        fs::remove_dir_all(&mirror)
+            .with_context(|| format!("Failed to remove '{}'", mirror.display()))?;
+        println!("Removed {}", origin);
+        removed += 1;
+        freed += size;
+    }
+    println!(
+        "Removed {} cached repositories, freeing {}",
+        removed,
+        human_size(freed)
+    );
+    Ok(())
+}
+
+fn mirrors() -> Result<Vec<PathBuf>> {
+    let dir = dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut mirrors = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.is_dir() && path.extension().is_some_and(|ext| ext == "git") {
+            mirrors.push(path);
+        }
+    }
+    mirrors.sort();
+    Ok(mirrors)
+}
+
+fn origin(mirror: &Path) -> String {
+    git(mirror, &["config", "remote.origin.url"])
+        .map(|url| redact_url(&url))
+        .unwrap_or_else(|_| "(unknown remote)".to_string())
+}
+
+/// Time since the mirror was last used.
+fn last_used(mirror: &Path) -> Duration {
+    fs::metadata(mirror.join(LAST_USED))
+        .or_else(|_| fs::metadata(mirror))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or_default()
+}
+
+fn describe_age(age: Duration) -> String {
+    match age.as_secs() / DAY.as_secs() {
+        0 => "used today".to_string(),
+        1 => "used 1 day ago".to_string(),
+        days => format!("used {} days ago", days),
+    }
+}
+
+fn disk_usage(path: &Path) -> usize {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len() as usize;
+    }
+    fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| disk_usage(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,116 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use crate::remote::{git, git_output};
+
+/// Files touched between two revisions, keyed by their path relative to the
+/// input folder.
+pub struct ChangeSet {
+    /// Unified diff of each changed file, when asked for
+    files: HashMap<String, Option<String>>,
+}
+
+/// Two revisions to compare, as given to `--diff`.
+pub struct Range {
+    pub base: String,
+    pub head: String,
+    /// `base...head`: compare against the merge base of the two
+    pub merge_base: bool,
+}
+
+impl Range {
+    /// Parses `base..head` or `base...head`; a missing side means `HEAD`,
+    /// just like in git.
+    pub fn parse(range: &str) -> Result<Self> {
+        let (base, head, merge_base) = match range.split_once("...") {
+            Some((base, head)) => (base, head, true),
+            None => match range.split_once("..") {
+                Some((base, head)) => (base, head, false),
+                None => bail!("Expected a range like main..feature, got '{}'", range),
+            },
+        };
+        let or_head = |rev: &str| if rev.is_empty() { "HEAD" } else { rev }.to_string();
+        Ok(Range {
+            base: or_head(base),
+            head: or_head(head),
+            merge_base,
+        })
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dots = if self.merge_base { "..." } else { ".." };
+        write!(f, "{}{}{}", self.base, dots, self.head)
+    }
+}
+
+impl ChangeSet {
+    /// Collects the files under `folder` that differ between `base` and
+    /// `head`, or between `base` and the working tree (uncommitted and
+    /// untracked files included) without a `head`. Deleted files have no
+    /// contents to show and are left out.
+    pub fn collect(folder: &str, base: &str, head: Option<&str>, with_diff: bool) -> Result<Self> {
+        let dir = Path::new(folder);
+        // `--relative` makes paths relative to `folder` and leaves out
+        // changes outside of it.
+        let mut args = vec![
+            "diff",
+            "--relative",
+            "--no-renames",
+            "--no-ext-diff",
+            "--diff-filter=d",
+        ];
+        args.push(base);
+        args.extend(head);
+
+        let mut files = HashMap::new();
+        for path in split_nul(&git(dir, &[&args[..], &["--name-only", "-z"]].concat())?) {
+            let diff = if with_diff {
+                Some(git_output(dir, &[&args[..], &["--", &path]].concat())?)
+            } else {
+                None
+            };
+            files.insert(path, diff);
+        }
+
+        if head.is_none() {
+            // Untracked files are new in full, so they need no diff.
+            let untracked = git(dir, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+            files.extend(split_nul(&untracked).into_iter().map(|path| (path, None)));
+        }
+        Ok(ChangeSet { files })
+    }
+
+    pub fn contains(&self, rel_path: &str) -> bool {
+        self.files.contains_key(rel_path)
+    }
+
+    /// Adds a file without a diff of its own.
+    pub fn insert(&mut self, rel_path: &str) {
+        self.files.entry(rel_path.to_string()).or_default();
+    }
+
+    pub fn diff(&self, rel_path: &str) -> Option<String> {
+        self.files.get(rel_path).cloned().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+}
+
+/// Resolves the common ancestor of `base` and `head`, for `base...head`.
+pub fn merge_base(folder: &str, base: &str, head: &str) -> Result<String> {
+    git(Path::new(folder), &["merge-base", base, head])
+}
+
+fn split_nul(output: &str) -> Vec<String> {
+    output
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -0,0 +1,325 @@
+use anyhow::{bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, ValueEnum};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::format::OutputFormat;
+use crate::tokens::Tokenizer;
+use crate::tree::TreeAnnotation;
+use crate::{Args, DEFAULT_INCLUDE};
+
+/// Name of the project config file, looked for in the input root and every
+/// directory above it.
+const FILE_NAME: &str = "repocat.toml";
+
+/// Keys a config file can set, each with the id of the flag it stands for.
+const KEYS: &[(&str, &str)] = &[
+    ("include", "include"),
+    ("exclude", "exclude"),
+    ("format", "format"),
+    ("template", "template"),
+    ("tokenizer", "tokenizer"),
+    ("stats", "stats"),
+    ("max-tokens", "max_tokens"),
+    ("priority", "priority"),
+    ("tree", "tree"),
+    ("git-info", "git_info"),
+];
+
+/// Settings of a config file or profile, named like the flags they stand
+/// in for.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Settings {
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    format: Option<String>,
+    template: Option<String>,
+    tokenizer: Option<String>,
+    stats: Option<bool>,
+    max_tokens: Option<usize>,
+    priority: Option<Vec<String>>,
+    tree: Option<String>,
+    git_info: Option<bool>,
+}
+
+impl Settings {
+    /// Checks the values clap would otherwise check on the command line.
+    fn validate(&self) -> Result<()> {
+        if let Some(format) = &self.format {
+            value_enum::<OutputFormat>("format", format)?;
+        }
+        if let Some(tokenizer) = &self.tokenizer {
+            value_enum::<Tokenizer>("tokenizer", tokenizer)?;
+        }
+        if let Some(tree) = &self.tree {
+            value_enum::<TreeAnnotation>("tree", tree)?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings from the global and project config files, and the profile
+/// picked from them.
+pub struct Config {
+    /// Config files looked at, global one first, and whether they exist
+    files: Vec<(&'static str, PathBuf, bool)>,
+    profile: Option<String>,
+    /// Every key set by a file, with its value and where it came from
+    values: BTreeMap<String, (toml::Value, String)>,
+}
+
+/// Where the global config lives: `$XDG_CONFIG_HOME/repocat/repocat.toml`,
+/// or `~/.config/repocat/repocat.toml`.
+fn global_path() -> Option<PathBuf> {
+    if let Some(config) = env::var_os("XDG_CONFIG_HOME").filter(|config| !config.is_empty()) {
+        return Some(PathBuf::from(config).join("repocat").join(FILE_NAME));
+    }
+    let home = env::var_os("HOME").or_else(|| env::var_os("USERPROFILE"))?;
+    Some(
+        PathBuf::from(home)
+            .join(".config")
+            .join("repocat")
+            .join(FILE_NAME),
+    )
+}
+
+/// The `repocat.toml` nearest to `start`, in it or any directory above.
+fn project_path(start: &Path) -> Option<PathBuf> {
+    let start = start.canonicalize().ok()?;
+    let dir = if start.is_file() {
+        start.parent()?
+    } else {
+        &start
+    };
+    dir.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+/// Reads the global config and the project one nearest to `start`, and
+/// layers them: the global file, then the project file, then `profile`
+/// from the global file and from the project file, later ones winning.
+pub fn load(start: &Path, profile: Option<&str>) -> Result<Config> {
+    let mut files = Vec::new();
+    if let Some(path) = global_path() {
+        let exists = path.is_file();
+        files.push(("Global", path, exists));
+    }
+    match project_path(start) {
+        // A global file found upward is not read twice.
+        Some(path) if !files.iter().any(|(_, global, _)| *global == path) => {
+            files.push(("Project", path, true))
+        }
+        _ => files.push(("Project", start.join(FILE_NAME), false)),
+    }
+
+    let mut layers = Vec::new();
+    let mut profiles = Vec::new();
+    for (kind, path, exists) in &files {
+        if !exists {
+            continue;
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read '{}'", path.display()))?;
+        let mut table: toml::Table = toml::from_str(&text)
+            .with_context(|| format!("Invalid config '{}'", path.display()))?;
+        let file_profiles = match table.remove("profiles") {
+            Some(toml::Value::Table(file_profiles)) => file_profiles,
+            Some(_) => bail!("'profiles' in '{}' must be a table", path.display()),
+            None => toml::Table::new(),
+        };
+        check(&table, &path.display().to_string())?;
+        layers.push((path.display().to_string(), table, path, *kind));
+        // Every profile is checked, so typos show up before they are used.
+        for (name, settings) in file_profiles {
+            let source = format!("profile '{}' in {}", name, path.display());
+            let toml::Value::Table(settings) = settings else {
+                bail!("{} must be a table", source);
+            };
+            check(&settings, &source)?;
+            if profile == Some(name.as_str()) {
+                profiles.push((source, settings, path, *kind));
+            }
+        }
+    }
+    if let Some(name) = profile {
+        if profiles.is_empty() {
+            let read: Vec<String> = files
+                .iter()
+                .filter(|(_, _, exists)| *exists)
+                .map(|(_, path, _)| format!("'{}'", path.display()))
+                .collect();
+            if read.is_empty() {
+                bail!("No profile '{}': no {} was found", name, FILE_NAME);
+            }
+            bail!("No profile '{}' in {}", name, read.join(" or "));
+        }
+    }
+
+    let mut values = BTreeMap::new();
+    for (source, mut table, path, kind) in layers.into_iter().chain(profiles) {
+        // Templates are found next to the file that names them.
+        if let Some(toml::Value::String(template)) = table.get_mut("template") {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let resolved = dir.join(&*template);
+            // A project file comes with the checkout, so it may only name
+            // templates inside the project, not any file on the machine.
+            if kind == "Project" {
+                let inside = resolved
+                    .canonicalize()
+                    .ok()
+                    .zip(dir.canonicalize().ok())
+                    .is_some_and(|(resolved, dir)| resolved.starts_with(dir));
+                if !inside {
+                    bail!(
+                        "Invalid settings in {}: template '{}' is not a file inside '{}'",
+                        source,
+                        template,
+                        dir.display()
+                    );
+                }
+            }
+            *template = resolved.to_string_lossy().into_owned();
+        }
+        for (key, value) in table {
+            values.insert(key, (value, source.clone()));
+        }
+    }
+    Ok(Config {
+        files,
+        profile: profile.map(str::to_string),
+        values,
+    })
+}
+
+/// Makes sure `table`, read from `source`, only holds known settings with
+/// valid values.
+fn check(table: &toml::Table, source: &str) -> Result<()> {
+    let settings: Settings = toml::Value::Table(table.clone())
+        .try_into()
+        .with_context(|| format!("Invalid settings in {}", source))?;
+    settings
+        .validate()
+        .with_context(|| format!("Invalid settings in {}", source))
+}
+
+impl Config {
+    /// Config files that were read.
+    pub fn sources(&self) -> impl Iterator<Item = &Path> {
+        self.files
+            .iter()
+            .filter(|(_, _, exists)| *exists)
+            .map(|(_, path, _)| path.as_path())
+    }
+
+    fn settings(&self) -> Settings {
+        let table: toml::Table = self
+            .values
+            .iter()
+            .map(|(key, (value, _))| (key.clone(), value.clone()))
+            .collect();
+        toml::Value::Table(table)
+            .try_into()
+            .expect("every layer was checked on loading")
+    }
+
+    /// Fills in every setting of `args` not given on the command line with
+    /// the configured value, if there is one.
+    pub fn apply(&self, args: &mut Args, matches: &ArgMatches) -> Result<()> {
+        let settings = self.settings();
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+        if let (true, Some(include)) = (unset("include"), settings.include) {
+            args.include = Some(include);
+        }
+        if let (true, Some(exclude)) = (unset("exclude"), settings.exclude) {
+            args.exclude = Some(exclude);
+        }
+        if let (true, Some(format)) = (unset("format"), settings.format) {
+            args.format = value_enum("format", &format)?;
+        }
+        if let (true, Some(template)) = (unset("template"), settings.template) {
+            args.template = Some(template);
+        }
+        if let (true, Some(tokenizer)) = (unset("tokenizer"), settings.tokenizer) {
+            args.tokenizer = value_enum("tokenizer", &tokenizer)?;
+        }
+        if let (true, Some(stats)) = (unset("stats"), settings.stats) {
+            args.stats = stats;
+        }
+        if let (true, Some(max_tokens)) = (unset("max_tokens"), settings.max_tokens) {
+            args.max_tokens = Some(max_tokens);
+        }
+        if let (true, Some(priority)) = (unset("priority"), settings.priority) {
+            args.priority = priority;
+        }
+        if let (true, Some(tree)) = (unset("tree"), settings.tree) {
+            args.tree = Some(value_enum("tree", &tree)?);
+        }
+        if let (true, Some(git_info)) = (unset("git_info"), settings.git_info) {
+            args.git_info = git_info;
+        }
+        Ok(())
+    }
+
+    /// Prints the effective settings as a config file, each with where it
+    /// came from.
+    pub fn show(&self) {
+        for (kind, path, exists) in &self.files {
+            let note = if *exists { "" } else { " (not found)" };
+            println!("# {} config: {}{}", kind, path.display(), note);
+        }
+        if let Some(profile) = &self.profile {
+            println!("# Profile: {}", profile);
+        }
+        println!();
+
+        let command = Args::command();
+        for (key, id) in KEYS {
+            let (value, source) = match self.values.get(*key) {
+                Some((value, source)) => (Some(value.clone()), source.as_str()),
+                None => (default_value(&command, id), "default"),
+            };
+            match value {
+                Some(value) => println!("{} = {}  # {}", key, value, source),
+                None => println!("# {} is not set", key),
+            }
+        }
+    }
+}
+
+/// Value a setting has when neither a config file nor a flag sets it.
+fn default_value(command: &clap::Command, id: &str) -> Option<toml::Value> {
+    let strings =
+        |values: &[&str]| toml::Value::Array(values.iter().map(|value| (*value).into()).collect());
+    match id {
+        "include" => Some(strings(DEFAULT_INCLUDE)),
+        "exclude" | "priority" => Some(strings(&[])),
+        "stats" | "git_info" => Some(false.into()),
+        _ => command
+            .get_arguments()
+            .find(|arg| arg.get_id() == id)?
+            .get_default_values()
+            .first()
+            .map(|value| value.to_string_lossy().into_owned().into()),
+    }
+}
+
+fn value_enum<T: ValueEnum>(key: &str, value: &str) -> Result<T> {
+    T::from_str(value, true).map_err(|_| {
+        let choices: Vec<String> = T::value_variants()
+            .iter()
+            .filter_map(|variant| Some(variant.to_possible_value()?.get_name().to_string()))
+            .collect();
+        anyhow::anyhow!(
+            "Invalid {} '{}', expected one of: {}",
+            key,
+            value,
+            choices.join(", ")
+        )
+    })
+}
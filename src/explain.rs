@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::filter::Filter;
+use crate::remote::git;
+use crate::tree::human_size;
+
+/// Ignore files read in every directory, from lowest to highest precedence.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore", ".rgignore"];
+
+/// Prints, for each of `paths` (relative to `folder` unless absolute),
+/// whether it would be part of the output and every rule that decides it:
+/// hidden names and ignore files for the directory walker, then the
+/// include and exclude patterns, then whether its contents are text.
+pub fn explain(folder: &str, paths: &[String], filter: &Filter) -> Result<()> {
+    let root = Path::new(folder);
+    for (position, given) in paths.iter().enumerate() {
+        if position > 0 {
+            println!();
+        }
+        let rel_path = relative_to(root, given)?;
+        let (verdict, lines) = explain_one(root, &rel_path, filter)?;
+        println!("{}: {}", rel_path, verdict);
+        for line in lines {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
+
+/// `/`-separated path of `given` inside `root`, refusing paths outside it.
+fn relative_to(root: &Path, given: &str) -> Result<String> {
+    let mut path = PathBuf::from(given);
+    if path.is_absolute() {
+        let root = root.canonicalize()?;
+        let absolute = path.canonicalize().unwrap_or(path);
+        path = match absolute.strip_prefix(&root) {
+            Ok(inside) => inside.to_path_buf(),
+            Err(_) => bail!("'{}' is not inside '{}'", given, root.display()),
+        };
+    }
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().into_owned()),
+            Component::CurDir => {}
+            _ => bail!("'{}' is not inside the input", given),
+        }
+    }
+    if parts.is_empty() {
+        bail!("'{}' is the input itself, not a file in it", given);
+    }
+    Ok(parts.join("/"))
+}
+
+fn explain_one(root: &Path, rel_path: &str, filter: &Filter) -> Result<(String, Vec<String>)> {
+    let path = root.join(rel_path);
+    let Ok(metadata) = fs::symlink_metadata(&path) else {
+        return Ok(("excluded".to_string(), vec!["does not exist".to_string()]));
+    };
+    let mut lines = Vec::new();
+    let mut reasons = Vec::new();
+
+    if metadata.is_dir() {
+        return Ok((
+            "excluded".to_string(),
+            vec!["is a directory; only files are concatenated".to_string()],
+        ));
+    }
+    if metadata.file_type().is_symlink() {
+        reasons.push("symlink".to_string());
+        lines.push("walker: symbolic links are not followed".to_string());
+    } else if let Some(hidden) = rel_path.split('/').find(|part| part.starts_with('.')) {
+        reasons.push("hidden".to_string());
+        lines.push(format!(
+            "walker: '{}' starts with a dot, and hidden files are skipped",
+            hidden
+        ));
+    } else if let Some(rule) = ignore_rule(root, &path)? {
+        reasons.push("gitignore".to_string());
+        lines.push(format!("walker: {}", rule));
+    } else if !walked(root, &path)? {
+        reasons.push("walker".to_string());
+        lines.push("walker: skipped while walking the folder".to_string());
+    } else {
+        lines.push("walker: not hidden or ignored".to_string());
+    }
+
+    let patterns = filter.explain(rel_path);
+    if !patterns.included {
+        reasons.push("--include".to_string());
+    }
+    lines.push(format!("include: {}", patterns.include));
+    if patterns.excluded {
+        reasons.push("--exclude".to_string());
+    }
+    lines.push(format!("exclude: {}", patterns.exclude));
+
+    if metadata.is_file() {
+        let contents = fs::read(&path).with_context(|| format!("Failed to read '{}'", rel_path))?;
+        let size = human_size(contents.len());
+        if contents.contains(&0) || std::str::from_utf8(&contents).is_err() {
+            reasons.push("binary".to_string());
+            lines.push(format!(
+                "contents: {} of binary or non-UTF-8 data, so it is skipped",
+                size
+            ));
+        } else {
+            lines.push(format!("contents: {} of text", size));
+        }
+    }
+
+    let verdict = if reasons.is_empty() {
+        "included".to_string()
+    } else {
+        format!("excluded ({})", reasons.join(", "))
+    };
+    Ok((verdict, lines))
+}
+
+/// The ignore file rule that hides `path` from the walker, if any. Ignore
+/// files are read the way the walker reads them: git's global excludes and
+/// `.git/info/exclude`, then `.gitignore`, `.ignore` and `.rgignore` in every
+/// directory from the top of the repository down to the file's, later ones
+/// taking precedence. `.gitignore` files only count inside a git repository.
+fn ignore_rule(root: &Path, path: &Path) -> Result<Option<String>> {
+    let root = root.canonicalize()?;
+    let path = path.canonicalize()?;
+    let top = git(&root, &["rev-parse", "--show-toplevel"])
+        .ok()
+        .and_then(|top| PathBuf::from(top).canonicalize().ok());
+    let in_repo = top.is_some();
+    let top = top.filter(|top| root.starts_with(top)).unwrap_or(root);
+    if !path.starts_with(&top) {
+        return Ok(None);
+    }
+
+    let mut matchers = Vec::new();
+    if in_repo {
+        let (global, _) = GitignoreBuilder::new(&top).build_global();
+        matchers.push(global);
+        let (exclude, _) = Gitignore::new(top.join(".git").join("info").join("exclude"));
+        matchers.push(exclude);
+    }
+    let parent = path.parent().unwrap_or(&top);
+    let mut dirs: Vec<&Path> = parent
+        .ancestors()
+        .take_while(|dir| dir.starts_with(&top))
+        .collect();
+    dirs.reverse();
+    for dir in dirs {
+        for name in IGNORE_FILES {
+            if *name == ".gitignore" && !in_repo {
+                continue;
+            }
+            let file = dir.join(name);
+            if file.is_file() {
+                let (matcher, _) = Gitignore::new(&file);
+                matchers.push(matcher);
+            }
+        }
+    }
+
+    for matcher in matchers.iter().rev() {
+        match matcher.matched_path_or_any_parents(&path, false) {
+            Match::Ignore(glob) => {
+                let source = glob
+                    .from()
+                    .map_or("git's global excludes".to_string(), |from| {
+                        from.display().to_string()
+                    });
+                return Ok(Some(format!(
+                    "ignored by '{}' in {}",
+                    glob.original(),
+                    source
+                )));
+            }
+            Match::Whitelist(_) => return Ok(None),
+            Match::None => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the walker yields `path`, descending only into the directories
+/// that lead to it.
+fn walked(root: &Path, path: &Path) -> Result<bool> {
+    let target = path.to_path_buf();
+    let walker = WalkBuilder::new(root)
+        .filter_entry(move |entry| target.starts_with(entry.path()))
+        .build();
+    for entry in walker {
+        if entry?.path() == path {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
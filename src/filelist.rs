@@ -0,0 +1,32 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+
+/// Reads the paths listed in `source`, a file or `-` for stdin. Paths are
+/// NUL-separated if the list contains a NUL (as from `git ls-files -z` or
+/// `fd -0`), one per line otherwise. Blank entries and repeats are dropped.
+pub fn read(source: &str) -> Result<Vec<String>> {
+    let bytes = if source == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Failed to read the file list from stdin")?;
+        bytes
+    } else {
+        fs::read(source).with_context(|| format!("Failed to read the file list '{}'", source))?
+    };
+    let text = String::from_utf8(bytes).context("The file list is not valid UTF-8")?;
+
+    let entries: Vec<&str> = if text.contains('\0') {
+        text.split('\0').collect()
+    } else {
+        text.lines().collect()
+    };
+    let mut seen = HashSet::new();
+    Ok(entries
+        .into_iter()
+        .filter(|entry| !entry.is_empty() && seen.insert(*entry))
+        .map(str::to_string)
+        .collect())
+}
@@ -0,0 +1,185 @@
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use std::path::Path;
+
+/// `--include` and `--exclude` patterns, compiled once and matched against
+/// paths relative to the input root with `.gitignore` semantics: patterns
+/// without a `/` match at any depth, ones with a `/` are anchored to the
+/// root, `*` stays within a directory while `**` crosses them, a trailing
+/// `/` matches everything below a directory, and a later `!pattern` takes
+/// files back out of the set.
+pub struct Filter {
+    include: Gitignore,
+    exclude: Gitignore,
+}
+
+impl Filter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Filter> {
+        Ok(Filter {
+            include: compile("--include", include)?,
+            exclude: compile("--exclude", exclude)?,
+        })
+    }
+
+    /// Whether the file at `rel_path` is included and not excluded.
+    pub fn matches(&self, rel_path: &str) -> bool {
+        let path = Path::new(rel_path);
+        self.include
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+            && !self
+                .exclude
+                .matched_path_or_any_parents(path, false)
+                .is_ignore()
+    }
+
+    /// Which patterns decide whether the file at `rel_path` is selected.
+    pub fn explain(&self, rel_path: &str) -> Explanation {
+        let path = Path::new(rel_path);
+        let (included, include) = match self.include.matched_path_or_any_parents(path, false) {
+            Match::Ignore(glob) => (true, format!("matches --include '{}'", glob.original())),
+            Match::Whitelist(glob) => (
+                false,
+                format!("taken back out by --include '{}'", glob.original()),
+            ),
+            Match::None => (false, "matches no --include pattern".to_string()),
+        };
+        let (excluded, exclude) = match self.exclude.matched_path_or_any_parents(path, false) {
+            Match::Ignore(glob) => (true, format!("matches --exclude '{}'", glob.original())),
+            Match::Whitelist(glob) => (false, format!("kept by --exclude '{}'", glob.original())),
+            Match::None => (false, "matches no --exclude pattern".to_string()),
+        };
+        Explanation {
+            included,
+            include,
+            excluded,
+            exclude,
+        }
+    }
+}
+
+/// `--priority` patterns, matched like those of a [`Filter`] but each on its
+/// own, as files are ranked by the first one they match.
+pub struct Priority {
+    patterns: Vec<Gitignore>,
+}
+
+impl Priority {
+    pub fn new(patterns: &[String]) -> Result<Priority> {
+        Ok(Priority {
+            patterns: patterns
+                .iter()
+                .map(|pattern| compile("--priority", std::slice::from_ref(pattern)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Position of the first pattern matching the file at `rel_path`.
+    pub fn rank(&self, rel_path: &str) -> Option<usize> {
+        let path = Path::new(rel_path);
+        self.patterns
+            .iter()
+            .position(|pattern| pattern.matched_path_or_any_parents(path, false).is_ignore())
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+}
+
+/// How the patterns of a [`Filter`] treat one file.
+pub struct Explanation {
+    pub included: bool,
+    /// The `--include` pattern that decided, or that none matched
+    pub include: String,
+    pub excluded: bool,
+    /// The `--exclude` pattern that decided, or that none matched
+    pub exclude: String,
+}
+
+/// Compiles `patterns`, refusing any that are malformed (including an
+/// unclosed `[`, which `.gitignore` files would take literally) so a typo
+/// fails loudly instead of silently matching nothing.
+fn compile(option: &str, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new("");
+    builder.allow_unclosed_class(false);
+    for pattern in patterns {
+        builder
+            .add_line(None, pattern)
+            .with_context(|| format!("Invalid {} pattern '{}'", option, pattern))?;
+    }
+    builder
+        .build()
+        .with_context(|| format!("Invalid {} patterns", option))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(include: &[&str], exclude: &[&str]) -> Filter {
+        let strings =
+            |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        Filter::new(&strings(include), &strings(exclude)).unwrap()
+    }
+
+    #[test]
+    fn slash_anchors_patterns_to_the_root() {
+        let anchored = filter(&["src/*.rs"], &[]);
+        assert!(anchored.matches("src/main.rs"));
+        assert!(!anchored.matches("src/bin/tool.rs"));
+        assert!(!anchored.matches("lib/src/main.rs"));
+
+        let anywhere = filter(&["*.rs"], &[]);
+        assert!(anywhere.matches("main.rs"));
+        assert!(anywhere.matches("src/bin/tool.rs"));
+        assert!(!anywhere.matches("src/main.rs.orig"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        let filter = filter(&["src/**/*.rs", "tests/**"], &[]);
+        assert!(filter.matches("src/main.rs"));
+        assert!(filter.matches("src/a/b/c.rs"));
+        assert!(filter.matches("tests/data/input.txt"));
+        assert!(!filter.matches("benches/a.rs"));
+    }
+
+    #[test]
+    fn negation_takes_files_back_out() {
+        let filter = filter(
+            &["*.rs", "!generated/*.rs"],
+            &["*_test.rs", "!keep_test.rs"],
+        );
+        assert!(filter.matches("src/lib.rs"));
+        assert!(!filter.matches("generated/bindings.rs"));
+        assert!(!filter.matches("src/parse_test.rs"));
+        assert!(filter.matches("src/keep_test.rs"));
+    }
+
+    #[test]
+    fn trailing_slash_matches_directories_only() {
+        let filter = filter(&["*"], &["tests/"]);
+        assert!(!filter.matches("tests/integration.rs"));
+        assert!(!filter.matches("crates/core/tests/unit.rs"));
+        assert!(filter.matches("tests"));
+        assert!(filter.matches("src/tests.rs"));
+    }
+
+    #[test]
+    fn clone_paths_match_relative_to_their_root() {
+        let root = Path::new("/tmp/.tmpAb12Cd");
+        let rel_path = crate::relative_path(&root.join("src").join("lib.rs"), root);
+        assert_eq!(rel_path, "src/lib.rs");
+        assert!(filter(&["src/*.rs"], &[]).matches(&rel_path));
+        assert!(!filter(&["tmp/**"], &[]).matches(&rel_path));
+        assert!(filter(&["*"], &[".*"]).matches(&rel_path));
+    }
+
+    #[test]
+    fn malformed_patterns_are_refused() {
+        let error = Filter::new(&["src/[ab.rs".to_string()], &[]).err().unwrap();
+        assert!(error.to_string().contains("src/[ab.rs"), "{}", error);
+    }
+}
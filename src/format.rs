@@ -0,0 +1,356 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::gitinfo::LastCommit;
+use crate::template::{self, Template};
+use crate::tokens::TokenCounter;
+use crate::{pack, FileRecord, RunInfo, Section};
+
+/// How each file is framed in the concatenated output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// `*** path` headers followed by the file with blank lines removed
+    #[default]
+    Text,
+    /// A single JSON array with one record per file
+    Json,
+    /// One JSON record per line
+    Jsonl,
+    /// Lossless framing that `repocat unpack` can turn back into files
+    Pack,
+    /// A heading per file followed by a language-tagged fenced code block
+    Markdown,
+    /// `<documents>` of `<document>` elements, the layout used in Claude prompts
+    Xml,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    path: &'a str,
+    size: usize,
+    lines: usize,
+    language: Option<&'static str>,
+    sha256: String,
+    content: &'a str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_commit: Option<JsonCommit<'a>>,
+    /// Where the file came from, when a ref was resolved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<JsonSource<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonSource<'a> {
+    repository: &'a str,
+    #[serde(rename = "ref")]
+    reference: &'a str,
+    commit: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonCommit<'a> {
+    sha: &'a str,
+    author: &'a str,
+    date: &'a str,
+}
+
+impl<'a> JsonRecord<'a> {
+    fn new(record: &'a FileRecord, info: &'a RunInfo) -> Self {
+        JsonRecord {
+            path: &record.rel_path,
+            size: record.size(),
+            lines: record.line_count(),
+            language: record.language(),
+            sha256: record.sha256(),
+            content: &record.contents,
+            truncated: record.truncated,
+            diff: record.diff.as_deref(),
+            last_commit: record.last_commit.as_ref().map(|commit| JsonCommit {
+                sha: &commit.sha,
+                author: &commit.author,
+                date: &commit.date,
+            }),
+            source: info.reference.as_deref().map(|reference| JsonSource {
+                repository: &info.repo,
+                reference,
+                commit: info.commit.as_deref(),
+            }),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Text written once before the first file.
+    pub fn header(self) -> String {
+        match self {
+            OutputFormat::Json => "[\n".to_string(),
+            OutputFormat::Pack => format!("{}\n", pack::MAGIC),
+            OutputFormat::Xml => "<documents>\n".to_string(),
+            OutputFormat::Text | OutputFormat::Jsonl | OutputFormat::Markdown => String::new(),
+        }
+    }
+
+    /// Frames extra context written after the header and before the first
+    /// file, or `None` if the format has no place for it.
+    pub fn preamble(self, section: &Section) -> Option<String> {
+        let Section { title, input, body } = section;
+        let heading = match input {
+            Some(input) => format!("{} ({})", title, input),
+            None => title.clone(),
+        };
+        match self {
+            OutputFormat::Text | OutputFormat::Pack => Some(format!("{}:\n{}\n", heading, body)),
+            OutputFormat::Markdown => Some(format!("## {}\n\n{}\n", heading, fenced(body, ""))),
+            OutputFormat::Xml => {
+                let mut tag: String = title
+                    .to_ascii_lowercase()
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect();
+                // `<source>` already holds the path of every document.
+                if tag == "source" {
+                    tag = "repository_source".to_string();
+                }
+                let input = input.as_ref().map_or(String::new(), |input| {
+                    format!(" input=\"{}\"", escape_xml(input))
+                });
+                Some(format!("<{tag}{input}>\n{}\n</{tag}>\n", xml_text(body)))
+            }
+            OutputFormat::Json | OutputFormat::Jsonl => None,
+        }
+    }
+
+    /// Text written between two consecutive files.
+    pub fn separator(self) -> String {
+        match self {
+            OutputFormat::Json => ",\n".to_string(),
+            OutputFormat::Markdown => "\n".to_string(),
+            OutputFormat::Text | OutputFormat::Jsonl | OutputFormat::Pack | OutputFormat::Xml => {
+                String::new()
+            }
+        }
+    }
+
+    /// Renders a single file; `index` is the file's position in the output.
+    pub fn render_file(self, index: usize, record: &FileRecord, info: &RunInfo) -> Result<String> {
+        Ok(match self {
+            OutputFormat::Text => {
+                // strip consecutive newlines and excess whitespace
+                let processed_lines: Vec<&str> = record
+                    .contents
+                    .split('\n')
+                    .map(str::trim_end)
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                let mut text = format!(
+                    "*** {}{}\n{}\n",
+                    record.path.to_string_lossy(),
+                    record
+                        .last_commit
+                        .as_ref()
+                        .map_or(String::new(), |commit| format!(" ({})", describe(commit))),
+                    processed_lines.join("\n")
+                );
+                if let Some(diff) = &record.diff {
+                    // Blank context lines are part of the diff, so keep it as is.
+                    text.push_str(&format!(
+                        "*** {} (diff)\n{}\n",
+                        record.path.to_string_lossy(),
+                        diff.strip_suffix('\n').unwrap_or(diff)
+                    ));
+                }
+                text
+            }
+            OutputFormat::Json => serde_json::to_string_pretty(&JsonRecord::new(record, info))?,
+            OutputFormat::Jsonl => {
+                format!("{}\n", serde_json::to_string(&JsonRecord::new(record, info))?)
+            }
+            // Packs hold files byte for byte and nothing else.
+            OutputFormat::Pack => pack::render_file(record)?,
+            OutputFormat::Markdown => {
+                let mut text = format!("## {}\n\n", record.rel_path);
+                if let Some(commit) = &record.last_commit {
+                    text.push_str(&format!("_{}_\n\n", describe(commit)));
+                }
+                text.push_str(&fenced(&record.contents, record.language().unwrap_or("")));
+                if let Some(diff) = &record.diff {
+                    text.push_str(&format!("\n{}", fenced(diff, "diff")));
+                }
+                text
+            }
+            OutputFormat::Xml => format!(
+                "<document index=\"{}\">\n<source>{}</source>\n{}<document_content>\n{}\n</document_content>\n{}</document>\n",
+                index + 1,
+                escape_xml(&record.rel_path),
+                record.last_commit.as_ref().map_or(String::new(), |commit| format!(
+                    "<last_commit sha=\"{}\" author=\"{}\" date=\"{}\"/>\n",
+                    escape_xml(&commit.sha),
+                    escape_xml(&commit.author),
+                    escape_xml(&commit.date)
+                )),
+                xml_text(&record.contents),
+                record.diff.as_deref().map_or(String::new(), |diff| format!(
+                    "<diff>\n{}\n</diff>\n",
+                    xml_text(diff.strip_suffix('\n').unwrap_or(diff))
+                ))
+            ),
+        })
+    }
+
+    /// Text written once after the last file.
+    pub fn footer(self) -> String {
+        match self {
+            OutputFormat::Json => "\n]\n".to_string(),
+            OutputFormat::Xml => "</documents>\n".to_string(),
+            OutputFormat::Text
+            | OutputFormat::Jsonl
+            | OutputFormat::Pack
+            | OutputFormat::Markdown => String::new(),
+        }
+    }
+}
+
+/// Frames files for output, using either a built-in format or, when one is
+/// given, a user template.
+pub struct Renderer<'a> {
+    pub format: OutputFormat,
+    pub template: Option<&'a Template>,
+    /// Needed only for templates that use `{{tokens}}`
+    pub counter: Option<&'a TokenCounter>,
+    pub info: &'a RunInfo,
+}
+
+impl Renderer<'_> {
+    pub fn header(&self) -> String {
+        match self.template {
+            Some(template) => template::fill(&template.header, &|name| self.run_value(name)),
+            None => self.format.header(),
+        }
+    }
+
+    pub fn preamble(&self, section: &Section) -> Option<String> {
+        match self.template {
+            Some(_) => OutputFormat::Text.preamble(section),
+            None => self.format.preamble(section),
+        }
+    }
+
+    pub fn separator(&self) -> String {
+        match self.template {
+            Some(template) => template::fill(&template.separator, &|name| self.run_value(name)),
+            None => self.format.separator(),
+        }
+    }
+
+    pub fn render_file(&self, index: usize, record: &FileRecord) -> Result<String> {
+        let Some(template) = self.template else {
+            return self.format.render_file(index, record, self.info);
+        };
+        Ok(template::fill(&template.file, &|name| match name {
+            "path" => record.rel_path.clone(),
+            "lang" => record.language().unwrap_or("").to_string(),
+            "lines" => record.line_count().to_string(),
+            "size" => record.size().to_string(),
+            "tokens" => self
+                .counter
+                .map_or(0, |counter| counter.count(&record.contents))
+                .to_string(),
+            "content" => record.contents.clone(),
+            "sha" => record.sha256(),
+            "index" => (index + 1).to_string(),
+            "diff" => record.diff.clone().unwrap_or_default(),
+            "last_commit" => commit_field(record, |commit| &commit.sha),
+            "last_author" => commit_field(record, |commit| &commit.author),
+            "last_date" => commit_field(record, |commit| &commit.date),
+            _ => self.run_value(name),
+        }))
+    }
+
+    pub fn footer(&self) -> String {
+        match self.template {
+            Some(template) => template::fill(&template.footer, &|name| self.run_value(name)),
+            None => self.format.footer(),
+        }
+    }
+
+    /// Whether files must be written whole, never truncated or cut in two.
+    pub fn is_lossless(&self) -> bool {
+        self.template.is_none() && self.format == OutputFormat::Pack
+    }
+
+    fn run_value(&self, name: &str) -> String {
+        match name {
+            "repo" => self.info.repo.clone(),
+            "commit" => self.info.commit.clone().unwrap_or_default(),
+            "ref" => self.info.reference.clone().unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// One-line summary of the last commit touching a file.
+fn describe(commit: &LastCommit) -> String {
+    format!(
+        "last changed in {} by {} on {}",
+        commit.short_sha(),
+        commit.author,
+        commit.date
+    )
+}
+
+fn commit_field(record: &FileRecord, field: fn(&LastCommit) -> &String) -> String {
+    record
+        .last_commit
+        .as_ref()
+        .map_or(String::new(), |commit| field(commit).clone())
+}
+
+/// Wraps `body` in a code fence longer than any backtick run inside it, so
+/// the block cannot be closed early by the file's own contents.
+fn fenced(body: &str, info: &str) -> String {
+    let longest_run = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let newline = if body.is_empty() || body.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    format!("{fence}{info}\n{body}{newline}{fence}\n")
+}
+
+fn escape_xml(text: &str) -> String {
+    xml_chars(text)
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Element text for arbitrary file contents: kept verbatim when it has no
+/// markup characters (`]]>` counts, as XML forbids it in text too),
+/// otherwise wrapped in CDATA (splitting any `]]>`).
+fn xml_text(text: &str) -> String {
+    let text = xml_chars(text);
+    if !text.contains(['<', '&']) && !text.contains("]]>") {
+        return text;
+    }
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// `text` with the control characters XML 1.0 forbids everywhere, even in
+/// CDATA (all below U+0020 but tab, newline and carriage return), replaced
+/// by their Unicode control pictures: form feed becomes `␌`, escape `␛`.
+fn xml_chars(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\t' | '\n' | '\r' => c,
+            '\0'..='\u{1f}' => char::from_u32(0x2400 + c as u32).unwrap_or('\u{fffd}'),
+            _ => c,
+        })
+        .collect()
+}
@@ -0,0 +1,126 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::remote::{self, git};
+
+/// The most recent commit that touched a file.
+#[derive(Clone, Debug)]
+pub struct LastCommit {
+    pub sha: String,
+    pub author: String,
+    /// Author date, `YYYY-MM-DD`
+    pub date: String,
+}
+
+impl LastCommit {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..self.sha.len().min(12)]
+    }
+}
+
+/// Describes the repository at `folder` as of `rev`: remote, branch,
+/// commit, commit date and, when `working_tree` is read, whether it has
+/// uncommitted changes. `remote_url` is used when given, otherwise the URL
+/// of `origin` if there is one.
+pub fn describe(
+    folder: &str,
+    rev: &str,
+    working_tree: bool,
+    remote_url: Option<&str>,
+) -> Result<String> {
+    let dir = Path::new(folder);
+    let commit = git(
+        dir,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)],
+    )?;
+    let date = git(dir, &["show", "-s", "--format=%cI", &commit])?;
+
+    let mut body = String::new();
+    let remote_url = match remote_url {
+        Some(url) => Some(url.to_string()),
+        None => git(dir, &["remote", "get-url", "origin"]).ok(),
+    };
+    if let Some(url) = remote_url {
+        body.push_str(&format!("Remote: {}\n", remote::redact_url(&url)));
+    }
+    if working_tree {
+        let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+        let branch = if branch == "HEAD" {
+            "(detached)"
+        } else {
+            &branch
+        };
+        body.push_str(&format!("Branch: {}\n", branch));
+    }
+    body.push_str(&format!("Commit: {}\nDate: {}\n", commit, date));
+    if working_tree {
+        let status = git(dir, &["status", "--porcelain", "--", "."])?;
+        let dirty = if status.is_empty() { "no" } else { "yes" };
+        body.push_str(&format!("Uncommitted changes: {}\n", dirty));
+    }
+    Ok(body)
+}
+
+/// Finds the last commit touching each of `paths` (relative to `folder`)
+/// in the history of `rev`, in a single pass over `git log` that stops as
+/// soon as every path has been seen.
+pub fn last_commits(
+    folder: &str,
+    rev: &str,
+    paths: &[&str],
+) -> Result<HashMap<String, LastCommit>> {
+    let mut wanted: HashSet<&str> = paths.iter().copied().collect();
+    let mut found = HashMap::new();
+    if wanted.is_empty() {
+        return Ok(found);
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(folder)
+        .args([
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--format=%x1e%H%x1f%an%x1f%ad",
+            "--date=short",
+            "--name-only",
+            "--no-renames",
+            "--relative",
+            rev,
+            "--",
+            ".",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run git")?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let mut current = None;
+    for line in BufReader::new(stdout).split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).into_owned();
+        if let Some(header) = line.strip_prefix('\u{1e}') {
+            let mut fields = header.split('\u{1f}').map(str::to_string);
+            current = Some(LastCommit {
+                sha: fields.next().unwrap_or_default(),
+                author: fields.next().unwrap_or_default(),
+                date: fields.next().unwrap_or_default(),
+            });
+        } else if let Some(commit) = &current {
+            if wanted.remove(line.as_str()) {
+                found.insert(line, commit.clone());
+                if wanted.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+    // Stopping early leaves git with nowhere to write.
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok(found)
+}
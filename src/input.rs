@@ -0,0 +1,231 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{archive, remote};
+
+/// URL schemes git can clone from.
+const GIT_SCHEMES: &[&str] = &[
+    "https://",
+    "http://",
+    "ssh://",
+    "git://",
+    "git+ssh://",
+    "ssh+git://",
+    "file://",
+];
+
+/// What an `--input` value refers to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Input {
+    /// A git remote, cloned before processing
+    Remote(String),
+    /// A folder (or single file) on disk
+    Local(String),
+    /// A zip or tar archive on disk, read without extracting it
+    Archive(String),
+}
+
+impl Input {
+    /// Label for the input when none is given: the repository, folder or
+    /// archive name.
+    pub fn default_label(&self) -> String {
+        let name = match self {
+            Input::Remote(url) => {
+                remote::repo_name(&remote::RemoteSpec::parse(url, None).url).to_string()
+            }
+            Input::Local(path) => Path::new(path)
+                .canonicalize()
+                .ok()
+                .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+                .unwrap_or_default(),
+            Input::Archive(path) => {
+                let name = Path::new(path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                archive::Kind::split(&name)
+                    .map_or(name.as_str(), |(stem, _)| stem)
+                    .to_string()
+            }
+        };
+        if is_label(&name) {
+            name
+        } else {
+            "input".to_string()
+        }
+    }
+}
+
+/// An `--input` value, `[LABEL=]SOURCE`.
+#[derive(Debug)]
+pub struct Labeled {
+    /// Name the input's files are placed under when there are several inputs
+    pub label: String,
+    pub input: Input,
+}
+
+impl Labeled {
+    /// Splits off a leading `LABEL=`, unless the whole value exists on disk
+    /// or what precedes the `=` could not be a label (as in URL queries).
+    pub fn parse(value: &str) -> Labeled {
+        if !Path::new(value).exists() {
+            if let Some((label, source)) = value.split_once('=') {
+                if is_label(label) && !source.is_empty() {
+                    return Labeled {
+                        label: label.to_string(),
+                        input: classify(source),
+                    };
+                }
+            }
+        }
+        let input = classify(value);
+        Labeled {
+            label: input.default_label(),
+            input,
+        }
+    }
+}
+
+/// Collects the comma-separated patterns given for each label in `values`
+/// (`LABEL=PATTERNS`, as passed to `--include-for` or `--exclude-for`),
+/// making sure every label names one of `inputs`.
+pub fn patterns_for(
+    option: &str,
+    values: &[String],
+    inputs: &[Labeled],
+) -> Result<HashMap<String, Vec<String>>> {
+    let mut patterns: HashMap<String, Vec<String>> = HashMap::new();
+    for value in values {
+        let Some((label, list)) = value.split_once('=') else {
+            bail!("Expected {} LABEL=PATTERNS, got '{}'", option, value);
+        };
+        if !inputs.iter().any(|input| input.label == label) {
+            bail!(
+                "{} names '{}', which is not the label of any input",
+                option,
+                label
+            );
+        }
+        patterns.entry(label.to_string()).or_default().extend(
+            list.split(',')
+                .filter(|pattern| !pattern.is_empty())
+                .map(str::to_string),
+        );
+    }
+    Ok(patterns)
+}
+
+/// Labels become the top directory of their input's files, so they are
+/// plain names: letters, digits, `-`, `_` and `.`, not starting with `.`.
+fn is_label(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with('.')
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Classifies an input the way `git clone` would: URLs with a known scheme
+/// and scp-like `[user@]host:path` addresses are remotes, and everything else
+/// (or anything that exists on disk) is a local path. Existing files named
+/// like an archive are read as archives.
+pub fn classify(input: &str) -> Input {
+    let lower = input.to_ascii_lowercase();
+    if GIT_SCHEMES.iter().any(|scheme| lower.starts_with(scheme)) {
+        return Input::Remote(input.to_string());
+    }
+    if !Path::new(input).exists() && is_scp_like(input) {
+        return Input::Remote(input.to_string());
+    }
+    if Path::new(input).is_file() && archive::Kind::detect(input).is_some() {
+        return Input::Archive(input.to_string());
+    }
+    Input::Local(input.to_string())
+}
+
+/// `git@github.com:org/repo.git` style: a colon before any slash, with a host
+/// longer than one character so Windows drive letters (`C:\repo`) are not
+/// mistaken for hosts.
+fn is_scp_like(input: &str) -> bool {
+    let Some((host, path)) = input.split_once(':') else {
+        return false;
+    };
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    host.len() > 1 && !host.contains(['/', '\\']) && !path.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_urls_and_scp_addresses_as_remotes() {
+        for input in [
+            "https://github.com/org/repo",
+            "HTTP://example.com/repo.git",
+            "ssh://git@host/repo.git",
+            "git://host/repo",
+            "file:///srv/repo.git",
+            "git@github.com:org/repo.git",
+            "host.example:repo",
+        ] {
+            assert_eq!(
+                classify(input),
+                Input::Remote(input.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_paths_as_local() {
+        for input in [
+            ".",
+            "src",
+            "./missing/folder",
+            "C:\\repo",
+            "C:/repo",
+            "dir/with:colon",
+            "trailing:",
+        ] {
+            assert_eq!(
+                classify(input),
+                Input::Local(input.to_string()),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_existing_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("bundle.tar.gz");
+        std::fs::write(&archive, "").unwrap();
+        let archive = archive.to_str().unwrap();
+        assert_eq!(classify(archive), Input::Archive(archive.to_string()));
+        // Only files on disk are archives; anything else is a path to walk.
+        assert_eq!(
+            classify("missing.zip"),
+            Input::Local("missing.zip".to_string())
+        );
+    }
+
+    #[test]
+    fn splits_labels_off_inputs() {
+        let labeled = Labeled::parse("lib=https://github.com/org/lib");
+        assert_eq!(labeled.label, "lib");
+        assert_eq!(
+            labeled.input,
+            Input::Remote("https://github.com/org/lib".to_string())
+        );
+        // What precedes an `=` in a URL is not a label.
+        let labeled = Labeled::parse("https://host/repo?ref=main");
+        assert_eq!(
+            labeled.input,
+            Input::Remote("https://host/repo?ref=main".to_string())
+        );
+    }
+}
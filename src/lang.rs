@@ -0,0 +1,55 @@
+use std::path::Path;
+
+/// Best-effort language name for a file, used to tag output records.
+///
+/// Names follow the identifiers commonly used for syntax highlighting
+/// (e.g. `rust`, `python`, `cuda`), so they can double as fence info strings.
+pub fn detect(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+    match file_name {
+        "Makefile" | "makefile" | "GNUmakefile" => return Some("makefile"),
+        "Dockerfile" => return Some("dockerfile"),
+        "CMakeLists.txt" => return Some("cmake"),
+        _ => {}
+    }
+
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let language = match extension.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "c" => "c",
+        "h" | "hpp" | "hh" | "hxx" | "cpp" | "cc" | "cxx" => "cpp",
+        "cu" | "cuh" => "cuda",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "rb" => "ruby",
+        "php" => "php",
+        "swift" => "swift",
+        "cs" => "csharp",
+        "scala" => "scala",
+        "lua" => "lua",
+        "sh" | "bash" => "bash",
+        "zsh" => "zsh",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "md" => "markdown",
+        "rst" => "rst",
+        "txt" => "text",
+        "cmake" => "cmake",
+        "proto" => "protobuf",
+        _ => return None,
+    };
+    Some(language)
+}
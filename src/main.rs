@@ -1,164 +1,1177 @@
-use anyhow::{Context, Result};
-use clap::Parser;
-use glob::Pattern;
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use ignore::WalkBuilder;
-use std::fs::File;
+use itertools::Itertools;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-#[cfg(feature = "git")]
-use git2::FetchOptions;
-#[cfg(feature = "git")]
-use tempfile::TempDir;
+mod apply;
+mod archive;
+mod auth;
+mod budget;
+mod cache;
+mod changes;
+mod config;
+mod explain;
+mod filelist;
+mod filter;
+mod format;
+mod gitinfo;
+mod input;
+mod lang;
+mod pack;
+mod remote;
+mod review;
+mod revision;
+mod split;
+mod template;
+mod tokens;
+mod tree;
+
+use changes::{ChangeSet, Range};
+use filter::{Filter, Priority};
+use format::{OutputFormat, Renderer};
+use gitinfo::LastCommit;
+use input::{Input, Labeled};
+use remote::RemoteSpec;
+use review::Request as ReviewRequest;
+use split::SplitLimit;
+use template::Template;
+use tokens::{TokenCounter, Tokenizer};
+use tree::TreeAnnotation;
+
+/// Patterns included when neither `--include` nor a config file says.
+const DEFAULT_INCLUDE: &[&str] = &[
+    "*.toml", "*.md", "*.py", "*.rs", "*.cpp", "*.h", "*.hpp", "*.c", "*.rst", "*.txt", "*.cuh",
+    "*.cu",
+];
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
-    /// GitHub repo URL or local folder path
-    #[arg(short, long)]
-    input: String,
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Git repository URL (https, ssh, git@host:path, git://, file://), local folder path,
+    /// or .zip, .tar, .tar.gz, .tar.xz or .tar.zst archive; repeat to combine several, each
+    /// optionally named with LABEL=SOURCE
+    #[arg(
+        short,
+        long,
+        required_unless_present = "files_from",
+        value_name = "[LABEL=]SOURCE"
+    )]
+    input: Vec<String>,
+
+    /// Concatenate exactly the files listed in this file (or - for stdin), one per line or
+    /// NUL-separated, relative to the --input folder (by default the current directory)
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = [
+            "include", "exclude", "include_for", "exclude_for",
+            "rev", "changed_since", "diff", "review",
+        ]
+    )]
+    files_from: Option<String>,
+
+    /// Branch, tag or commit to check out when cloning a remote repository
+    #[arg(long = "ref", value_name = "REF")]
+    reference: Option<String>,
+
+    /// Only clone and process this directory (or file) of a remote repository
+    #[arg(long)]
+    subdir: Option<String>,
+
+    /// Clone remote repositories afresh instead of through the on-disk cache
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Re-create the cached copy of a remote repository from scratch
+    #[arg(long, conflicts_with = "no_cache")]
+    refresh: bool,
+
+    /// Read a local repository as of this revision instead of its working tree
+    #[arg(long, value_name = "REV")]
+    rev: Option<String>,
+
+    /// Only include files changed since this revision, uncommitted and untracked ones included
+    #[arg(long, value_name = "REV", conflicts_with_all = ["diff", "rev"])]
+    changed_since: Option<String>,
+
+    /// Only include files changed between two revisions (BASE..HEAD or BASE...HEAD), as of HEAD
+    #[arg(long, value_name = "RANGE", conflicts_with = "rev")]
+    diff: Option<String>,
+
+    /// Bundle the commits, combined diff and changed files of BASE..HEAD (or BASE...HEAD) for review
+    #[arg(
+        long,
+        value_name = "RANGE",
+        conflicts_with_all = ["changed_since", "diff", "rev", "reference"]
+    )]
+    review: Option<String>,
+
+    /// With --review, also include the files that mention a changed file's module by name
+    #[arg(long, requires = "review")]
+    related: bool,
+
+    /// Add the unified diff of every file after its contents (needs --changed-since, --diff or --review)
+    #[arg(long)]
+    with_diff: bool,
 
     /// Output file name
     #[arg(short, long, default_value = "concatenated_output.txt")]
     output: String,
 
-    /// Glob patterns to include files (e.g., "*.rs,*.toml")
-    #[arg(short, long, use_value_delimiter = true, value_delimiter = ',')]
+    /// Gitignore-style patterns, relative to the input root, of files to include (e.g., "*.rs,src/**")
+    #[arg(long, use_value_delimiter = true, value_delimiter = ',')]
     include: Option<Vec<String>>,
 
-    /// Glob patterns to exclude files (e.g., "*.md,*.txt")
+    /// Gitignore-style patterns, relative to the input root, of files to exclude (e.g., "tests/,*.md")
     #[arg(short, long, use_value_delimiter = true, value_delimiter = ',')]
     exclude: Option<Vec<String>>,
+
+    /// Gitignore-style patterns to include for one input instead of --include (e.g., "lib=*.rs,*.toml")
+    #[arg(long, value_name = "LABEL=PATTERNS")]
+    include_for: Vec<String>,
+
+    /// Gitignore-style patterns to exclude for one input instead of --exclude (e.g., "lib=tests/")
+    #[arg(long, value_name = "LABEL=PATTERNS")]
+    exclude_for: Vec<String>,
+
+    /// Print why a file is or is not included instead of writing any output (repeatable)
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["files_from", "rev", "changed_since", "diff", "review"]
+    )]
+    explain: Vec<String>,
+
+    /// Apply this [profiles.NAME] table of repocat.toml on top of its other settings
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Template file with header, file, separator and footer sections; replaces --format
+    #[arg(long)]
+    template: Option<String>,
+
+    /// Print token counts per file, per directory and in total
+    #[arg(long)]
+    stats: bool,
+
+    /// Tokenizer used to count tokens
+    #[arg(long, value_enum, default_value_t = Tokenizer::O200k)]
+    tokenizer: Tokenizer,
+
+    /// Keep the output within this many tokens, truncating or omitting files
+    #[arg(long)]
+    max_tokens: Option<usize>,
+
+    /// Gitignore-style patterns ranked after READMEs and entry points when budgeting (e.g., "src/,*.py")
+    #[arg(long, use_value_delimiter = true, value_delimiter = ',')]
+    priority: Vec<String>,
+
+    /// Split the output into numbered chunks of at most this many tokens
+    #[arg(long, conflicts_with = "split_bytes")]
+    split_tokens: Option<usize>,
+
+    /// Split the output into numbered chunks of at most this many bytes
+    #[arg(long)]
+    split_bytes: Option<usize>,
+
+    /// Add the remote, branch, commit, date and dirty status, and every file's last commit
+    #[arg(long)]
+    git_info: bool,
+
+    /// Prepend a directory tree of the included files, optionally annotated
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "plain")]
+    tree: Option<TreeAnnotation>,
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
-
-    let default_include = vec![
-        "*.toml".to_string(),
-        "*.md".to_string(),
-        "*.py".to_string(),
-        "*.rs".to_string(),
-        "*.cpp".to_string(),
-        "*.h".to_string(),
-        "*.hpp".to_string(),
-        "*.c".to_string(),
-        "*.rst".to_string(),
-        "*.txt".to_string(),
-        "*.cuh".to_string(),
-        "*.cu".to_string(),
-    ];
-
-    let include = args.include.unwrap_or(default_include);
-    let exclude = args.exclude.unwrap_or_default();
-
-    if args.input.starts_with("https://github.com") {
-        process_github_repo(&args.input, &args.output, &include, &exclude)?;
-    } else {
-        process_local_folder(&args.input, &args.output, &include, &exclude)?;
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Recreate the files stored in an output written with `--format pack`
+    Unpack {
+        /// Output file written with `--format pack`
+        file: String,
+        /// Directory to recreate the files in
+        dir: String,
+    },
+    /// Write the files of an edited `--format pack` output back onto a tree
+    Apply {
+        /// Edited output written with `--format pack`
+        file: String,
+        /// Directory the paths in the output are relative to
+        #[arg(long, default_value = ".")]
+        root: String,
+        /// Only print the diff, without touching any files
+        #[arg(long)]
+        dry_run: bool,
+        /// Create files that do not exist under the root yet
+        #[arg(long)]
+        create: bool,
+        /// Delete files present in this unedited output but dropped from the edited one
+        #[arg(long, value_name = "ORIGINAL")]
+        delete_missing: Option<String>,
+    },
+    /// Inspect or clear the cache of remote repositories
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Inspect the settings read from repocat.toml files
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the effective settings and the file each comes from
+    Show {
+        /// Folder to look for repocat.toml from
+        #[arg(default_value = ".")]
+        dir: String,
+        /// Apply this profile of the config files
+        #[arg(long)]
+        profile: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// List cached repositories with their size and when they were last used
+    List,
+    /// Remove cached repositories
+    Prune {
+        /// Only remove repositories not used in this many days
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+    },
+}
+
+/// Settings shared by every kind of input.
+struct Options {
+    output: String,
+    /// Compiled `--include` and `--exclude` patterns of the current input
+    filter: Filter,
+    format: OutputFormat,
+    template: Option<Template>,
+    stats: bool,
+    tokenizer: Tokenizer,
+    max_tokens: Option<usize>,
+    priority: Priority,
+    split: Option<SplitLimit>,
+    tree: Option<TreeAnnotation>,
+    git_info: bool,
+    /// Clone remote repositories through the on-disk cache
+    cache: bool,
+    refresh: bool,
+    /// Files to restrict the output to, from `--changed-since` or `--diff`
+    changes: Option<ChangeSet>,
+}
+
+impl Options {
+    /// Whether the file at `rel_path` inside the input is part of the
+    /// output.
+    fn selects(&self, rel_path: &str) -> bool {
+        self.filter.matches(rel_path)
+            && self
+                .changes
+                .as_ref()
+                .is_none_or(|changes| changes.contains(rel_path))
     }
 
-    println!(
-        "All matching files have been concatenated into '{}'",
-        args.output
-    );
-    Ok(())
+    /// Diff to show alongside the file at `rel_path`, if asked for.
+    fn diff(&self, rel_path: &str) -> Option<String> {
+        self.changes.as_ref()?.diff(rel_path)
+    }
 }
 
-fn process_github_repo(
-    repo_url: &str,
-    output_file: &str,
-    include: &[String],
-    exclude: &[String],
-) -> Result<()> {
-    let temp_dir = tempfile::tempdir()?;
-    let repo_path = temp_dir.path();
+/// Where the files of a run came from.
+pub struct RunInfo {
+    /// Input as given on the command line
+    pub repo: String,
+    /// Commit checked out in the input, if it is a git repository
+    pub commit: Option<String>,
+    /// Ref the user asked for, if any
+    pub reference: Option<String>,
+    /// Sections written before the files, such as the commits and diff of
+    /// a review
+    pub context: Vec<Section>,
+}
 
-    println!("Cloning repository...");
+/// Extra context written before the files.
+pub struct Section {
+    pub title: String,
+    /// Label of the input the section is about, when there are several
+    pub input: Option<String>,
+    pub body: String,
+}
 
-    // Try using native Git CLI first
-    let clone_result = Command::new("git")
-        .args(&["clone", "--depth", "1", repo_url])
-        .arg(repo_path)
-        .output();
+impl Section {
+    pub fn new(title: &str, body: String) -> Self {
+        Section {
+            title: title.to_string(),
+            input: None,
+            body,
+        }
+    }
+}
+
+/// Files read from an input, ready to be written out.
+struct Collected {
+    records: Vec<FileRecord>,
+    /// Every directory seen while collecting the files
+    dirs: Vec<String>,
+    info: RunInfo,
+}
+
+/// A file selected for output, with its contents loaded.
+pub struct FileRecord {
+    /// Path as encountered while walking the input
+    pub path: PathBuf,
+    /// Path relative to the input root, always `/`-separated
+    pub rel_path: String,
+    pub contents: String,
+    /// Whether `contents` was cut down to fit a token budget
+    pub truncated: bool,
+    /// Unified diff of the file, with `--with-diff`
+    pub diff: Option<String>,
+    /// Last commit touching the file, with `--git-info`
+    pub last_commit: Option<LastCommit>,
+}
+
+impl FileRecord {
+    pub fn size(&self) -> usize {
+        self.contents.len()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.contents.lines().count()
+    }
+
+    pub fn language(&self) -> Option<&'static str> {
+        lang::detect(&self.path)
+    }
 
-    match clone_result {
-        Ok(output) if output.status.success() => {
-            println!("Successfully cloned using native Git CLI");
+    pub fn sha256(&self) -> String {
+        format!("{:x}", Sha256::digest(self.contents.as_bytes()))
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit());
+
+    if let Some(command) = args.command {
+        return match command {
+            Commands::Unpack { file, dir } => pack::unpack(&file, &dir),
+            Commands::Apply {
+                file,
+                root,
+                dry_run,
+                create,
+                delete_missing,
+            } => apply::apply(
+                &file,
+                &apply::ApplyOptions {
+                    root: &root,
+                    dry_run,
+                    create,
+                    delete_missing: delete_missing.as_deref(),
+                },
+            ),
+            Commands::Cache { action } => match action {
+                CacheAction::List => cache::list(),
+                CacheAction::Prune { older_than } => cache::prune(older_than),
+            },
+            Commands::Config { action } => match action {
+                ConfigAction::Show { dir, profile } => {
+                    config::load(Path::new(&dir), profile.as_deref())?.show();
+                    Ok(())
+                }
+            },
+        };
+    }
+
+    // The project config is looked for from the first local input.
+    let start = args
+        .input
+        .iter()
+        .find_map(|value| match Labeled::parse(value).input {
+            Input::Local(path) => Some(path),
+            _ => None,
+        })
+        .unwrap_or_else(|| ".".to_string());
+    let config = config::load(Path::new(&start), args.profile.as_deref())?;
+    for path in config.sources() {
+        println!("Using settings from '{}'", path.display());
+    }
+    config.apply(&mut args, &matches)?;
+
+    if args.with_diff
+        && args.changed_since.is_none()
+        && args.diff.is_none()
+        && args.review.is_none()
+    {
+        bail!("--with-diff needs --changed-since, --diff or --review");
+    }
+    let review = match &args.review {
+        Some(range) => Some(ReviewRequest {
+            range: Range::parse(range)?,
+            related: args.related,
+            with_diff: args.with_diff,
+        }),
+        None => None,
+    };
+
+    let include = args.include.clone().unwrap_or_else(|| {
+        DEFAULT_INCLUDE
+            .iter()
+            .map(|pattern| pattern.to_string())
+            .collect()
+    });
+    let exclude = args.exclude.clone().unwrap_or_default();
+    let mut options = Options {
+        output: args.output.clone(),
+        filter: Filter::new(&include, &exclude)?,
+        format: args.format,
+        template: args.template.as_deref().map(Template::load).transpose()?,
+        stats: args.stats,
+        tokenizer: args.tokenizer,
+        max_tokens: args.max_tokens,
+        priority: Priority::new(&args.priority)?,
+        split: args
+            .split_tokens
+            .map(SplitLimit::Tokens)
+            .or(args.split_bytes.map(SplitLimit::Bytes)),
+        tree: args.tree,
+        git_info: args.git_info,
+        cache: !args.no_cache,
+        refresh: args.refresh,
+        changes: None,
+    };
+
+    let mut inputs: Vec<Labeled> = args
+        .input
+        .iter()
+        .map(|value| Labeled::parse(value))
+        .collect();
+    if inputs.is_empty() {
+        // Only `--files-from` goes without an input.
+        inputs.push(Labeled::parse("."));
+    }
+    if inputs.len() > 1 {
+        if args.files_from.is_some()
+            || args.reference.is_some()
+            || args.subdir.is_some()
+            || args.rev.is_some()
+            || args.changed_since.is_some()
+            || args.diff.is_some()
+            || review.is_some()
+        {
+            bail!("--files-from, --ref, --subdir, --rev, --changed-since, --diff and --review only apply to a single --input");
         }
-        _ => {
-            println!("Native Git CLI failed, falling back to git2 library");
-            #[cfg(feature = "git")]
+        for (position, input) in inputs.iter().enumerate() {
+            if inputs[..position]
+                .iter()
+                .any(|other| other.label == input.label)
             {
-                let mut binding = FetchOptions::default();
-                binding.depth(1);
-                git2::build::RepoBuilder::new()
-                    .fetch_options(binding)
-                    .clone(repo_url, repo_path)?;
+                bail!(
+                    "Several inputs are labelled '{}', tell them apart with LABEL=SOURCE",
+                    input.label
+                );
+            }
+        }
+    }
+    let include_for = input::patterns_for("--include-for", &args.include_for, &inputs)?;
+    let exclude_for = input::patterns_for("--exclude-for", &args.exclude_for, &inputs)?;
+
+    // Every input's patterns are checked before anything is cloned or read.
+    let filters = inputs
+        .iter()
+        .map(|input| {
+            Filter::new(
+                include_for.get(&input.label).unwrap_or(&include),
+                exclude_for.get(&input.label).unwrap_or(&exclude),
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if !args.explain.is_empty() {
+        let [Labeled {
+            input: Input::Local(path),
+            ..
+        }] = inputs.as_slice()
+        else {
+            bail!("--explain only applies to a single local folder");
+        };
+        return explain::explain(path, &args.explain, &filters[0]);
+    }
+
+    let mut collected = Vec::new();
+    for (Labeled { label, input }, filter) in inputs.into_iter().zip(filters) {
+        options.filter = filter;
+        let files = collect(input, &args, review.as_ref(), &mut options)?;
+        collected.push((label, files));
+    }
+    let files = match collected.len() {
+        1 => collected.pop().expect("one input").1,
+        _ => merge(collected),
+    };
+    if files.records.is_empty() {
+        println!("No files matched; --explain <path> shows why a file was left out");
+    }
+    write_output(files.records, &files.dirs, &files.info, &options)?;
+
+    if options.split.is_none() {
+        println!(
+            "All matching files have been concatenated into '{}'",
+            options.output
+        );
+    }
+    Ok(())
+}
+
+/// Reads the files of one input, following the revision and change
+/// options of `args`.
+fn collect(
+    input: Input,
+    args: &Args,
+    review: Option<&ReviewRequest>,
+    options: &mut Options,
+) -> Result<Collected> {
+    if args.files_from.is_some() && !matches!(input, Input::Local(_)) {
+        bail!("--files-from only applies to a local folder");
+    }
+    match input {
+        Input::Remote(url) => {
+            if args.rev.is_some() {
+                bail!("--rev only applies to local repositories, use --ref for remote ones");
+            }
+            if args.changed_since.is_some() || args.diff.is_some() {
+                bail!("--changed-since and --diff only apply to local repositories");
+            }
+            let mut spec = RemoteSpec::parse(&url, args.reference.as_deref());
+            if let Some(subdir) = &args.subdir {
+                spec.subdir = Some(subdir.clone());
+            }
+            if let Some(request) = review {
+                spec.reference = Some(request.range.head.clone());
+                spec.base = Some(request.range.base.clone());
+            }
+            // Last commits per file need more than a shallow clone.
+            spec.history = options.git_info;
+            process_remote_repo(&spec, review, options)
+        }
+        Input::Local(path) => {
+            if args.reference.is_some() || args.subdir.is_some() {
+                bail!("--ref and --subdir only apply to remote repositories");
             }
-            #[cfg(not(feature = "git"))]
+            let mut rev = args.rev.clone();
+            let mut reference = None;
+            let mut context = Vec::new();
+            if let Some(base) = &args.changed_since {
+                options.changes = Some(ChangeSet::collect(&path, base, None, args.with_diff)?);
+            }
+            if let Some(range) = &args.diff {
+                let range = Range::parse(range)?;
+                let base = if range.merge_base {
+                    changes::merge_base(&path, &range.base, &range.head)?
+                } else {
+                    range.base
+                };
+                options.changes = Some(ChangeSet::collect(
+                    &path,
+                    &base,
+                    Some(&range.head),
+                    args.with_diff,
+                )?);
+                // Changed files are shown as they are at the head revision.
+                rev = Some(range.head);
+            }
+            if let Some(request) = review {
+                let review = review::prepare(&path, request)?;
+                options.changes = Some(review.changes);
+                context = review.sections;
+                reference = Some(request.range.to_string());
+                // Read like --rev, through the git CLI in builds without libgit2.
+                rev = Some(request.range.head.clone());
+            }
+            if let Some(changes) = &options.changes {
+                println!("Found {} changed files", changes.len());
+            }
+
+            let mut info = RunInfo {
+                repo: path.clone(),
+                commit: None,
+                reference: reference.or(rev.clone()),
+                context,
+            };
+            match (&rev, &args.files_from) {
+                (Some(rev), _) => process_revision(&path, rev, info, options),
+                (None, Some(list)) => {
+                    info.commit = head_commit(&path);
+                    process_file_list(&path, &filelist::read(list)?, info, options)
+                }
+                (None, None) => {
+                    info.commit = head_commit(&path);
+                    process_local_folder(&path, info, options)
+                }
+            }
+        }
+        Input::Archive(path) => {
+            if args.reference.is_some() || args.subdir.is_some() {
+                bail!("--ref and --subdir only apply to remote repositories");
+            }
+            if args.rev.is_some()
+                || args.changed_since.is_some()
+                || args.diff.is_some()
+                || review.is_some()
             {
-                return Err(anyhow::anyhow!("Git support is not enabled and native Git CLI failed. Please use a local folder path instead."));
+                bail!("--rev, --changed-since, --diff and --review need a git repository, not an archive");
+            }
+            if options.git_info {
+                println!("No git metadata for '{}': it is an archive", path);
             }
+            let contents = archive::read(&path, options)?;
+            let info = RunInfo {
+                repo: path,
+                commit: None,
+                reference: None,
+                context: Vec::new(),
+            };
+            Ok(Collected {
+                records: contents.records,
+                dirs: contents.dirs,
+                info,
+            })
+        }
+    }
+}
+
+/// Combines the files of several inputs, each under a directory named
+/// after its label, with an `Inputs` section saying where they came from.
+fn merge(inputs: Vec<(String, Collected)>) -> Collected {
+    let mut records = Vec::new();
+    let mut dirs = Vec::new();
+    let mut sources = String::new();
+    let mut context = Vec::new();
+    for (label, files) in &inputs {
+        let info = &files.info;
+        sources.push_str(&format!("{}: {}", label, info.repo));
+        if let Some(reference) = &info.reference {
+            sources.push_str(&format!(" at {}", reference));
+        }
+        if let Some(commit) = &info.commit {
+            sources.push_str(&format!(" ({})", commit));
+        }
+        sources.push('\n');
+        for section in &info.context {
+            context.push(Section {
+                title: section.title.clone(),
+                input: Some(label.clone()),
+                body: section.body.clone(),
+            });
         }
     }
+    context.insert(0, Section::new("Inputs", sources));
 
-    process_local_folder(repo_path.to_str().unwrap(), output_file, include, exclude)
+    let labels = inputs.iter().map(|(label, _)| label.as_str()).join(", ");
+    for (label, files) in inputs {
+        dirs.push(label.clone());
+        dirs.extend(files.dirs.iter().map(|dir| format!("{}/{}", label, dir)));
+        for mut record in files.records {
+            record.path = Path::new(&label).join(&record.rel_path);
+            record.rel_path = format!("{}/{}", label, record.rel_path);
+            records.push(record);
+        }
+    }
+    Collected {
+        records,
+        dirs,
+        info: RunInfo {
+            repo: labels,
+            commit: None,
+            reference: None,
+            context,
+        },
+    }
 }
 
-fn should_process_file(path: &Path, include: &[String], exclude: &[String]) -> bool {
-    let path_str = path.to_string_lossy();
+fn process_remote_repo(
+    spec: &RemoteSpec,
+    review: Option<&ReviewRequest>,
+    options: &mut Options,
+) -> Result<Collected> {
+    if let Some(subdir) = &spec.subdir {
+        pack::sanitize_path(subdir)?;
+    }
+    let temp_dir = tempfile::tempdir()?;
+    let repo_path = temp_dir.path();
+    let commit = if options.cache {
+        match cache::checkout(spec, repo_path, options.refresh) {
+            Ok(commit) => commit,
+            Err(error) => {
+                println!(
+                    "Could not use the clone cache, cloning afresh: {}",
+                    auth::redact(&format!("{:#}", error))
+                );
+                fs::remove_dir_all(repo_path)?;
+                fs::create_dir_all(repo_path)?;
+                remote::clone(spec, repo_path)?
+            }
+        }
+    } else {
+        remote::clone(spec, repo_path)?
+    };
 
-    let included = include.iter().any(|pattern| {
-        Pattern::new(pattern)
-            .map(|p| p.matches(&path_str))
-            .unwrap_or(false)
-    });
+    let folder = match &spec.subdir {
+        Some(subdir) => pack::safe_join(repo_path, subdir)?,
+        None => repo_path.to_path_buf(),
+    };
+    if !folder.exists() {
+        bail!(
+            "'{}' does not exist in the repository",
+            spec.subdir.as_deref().unwrap_or_default()
+        );
+    }
 
-    let excluded = exclude.iter().any(|pattern| {
-        Pattern::new(pattern)
-            .map(|p| p.matches(&path_str))
-            .unwrap_or(false)
-    });
+    let folder = folder.to_str().unwrap();
+    let mut info = RunInfo {
+        repo: remote::redact_url(&spec.url),
+        commit: Some(commit),
+        reference: spec.reference.clone(),
+        context: Vec::new(),
+    };
+    if let Some(request) = review {
+        // The clone has the head checked out and the base stored under
+        // `BASE_REF`, whatever they were called on the remote.
+        let local = ReviewRequest {
+            range: Range {
+                base: remote::BASE_REF.to_string(),
+                head: "HEAD".to_string(),
+                merge_base: request.range.merge_base,
+            },
+            ..*request
+        };
+        let review = review::prepare(folder, &local)?;
+        options.changes = Some(review.changes);
+        info.context = review.sections;
+        info.reference = Some(request.range.to_string());
+    }
+    process_local_folder(folder, info, options)
+}
 
-    included && !excluded
+/// Concatenates the files under `folder_path` as of `rev`, described by
+/// `info` (whose commit is filled in) in the output.
+fn process_revision(
+    folder_path: &str,
+    rev: &str,
+    info: RunInfo,
+    options: &Options,
+) -> Result<Collected> {
+    let mut snapshot = revision::snapshot(folder_path, rev, options)?;
+    let mut info = RunInfo {
+        commit: Some(snapshot.commit),
+        ..info
+    };
+    if options.git_info {
+        add_git_info(folder_path, rev, false, &mut info, &mut snapshot.records)?;
+    }
+    Ok(Collected {
+        records: snapshot.records,
+        dirs: snapshot.dirs,
+        info,
+    })
 }
 
-fn process_file(file_path: &Path) -> Result<String> {
+/// Reads the file at `file_path`, or `None` if it is not text.
+fn process_file(file_path: &Path, root: &Path) -> Result<Option<FileRecord>> {
     let mut file = File::open(file_path)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)?;
-    // strip consecutive newlines and excess whitespace
-    let processed_lines: Vec<String> = contents
-        .split('\n')
-        .map(str::trim_end)
-        .filter(|s| !s.is_empty())
-        .map(String::from)
-        .collect();
-    Ok(format!(
-        "*** {}\n{}",
-        file_path.to_str().unwrap(),
-        processed_lines.join("\n")
-    ))
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let rel_path = relative_path(file_path, root);
+    let Some(contents) = text(bytes, &rel_path) else {
+        return Ok(None);
+    };
+    Ok(Some(FileRecord {
+        path: file_path.to_path_buf(),
+        rel_path,
+        contents,
+        truncated: false,
+        diff: None,
+        last_commit: None,
+    }))
+}
+
+/// `bytes` as text, or `None` with a note if they hold a NUL byte or are
+/// not UTF-8, which cannot be concatenated.
+fn text(bytes: Vec<u8>, rel_path: &str) -> Option<String> {
+    if bytes.contains(&0) {
+        println!("Skipping {}: binary file", rel_path);
+        return None;
+    }
+    match String::from_utf8(bytes) {
+        Ok(contents) => Some(contents),
+        Err(_) => {
+            println!("Skipping {}: not valid UTF-8", rel_path);
+            None
+        }
+    }
+}
+
+/// `/`-separated path of `path` relative to `root`, or its file name if
+/// `root` is the file itself.
+fn relative_path(path: &Path, root: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    if relative.as_os_str().is_empty() {
+        return path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+    }
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .join("/")
 }
 
+/// Concatenates the files under `folder_path`, described by `info` in the
+/// output.
 fn process_local_folder(
     folder_path: &str,
-    output_file: &str,
-    include: &[String],
-    exclude: &[String],
-) -> Result<()> {
-    let mut output = File::create(output_file).context("Failed to create output file")?;
+    mut info: RunInfo,
+    options: &Options,
+) -> Result<Collected> {
+    let (mut records, dirs) = walk_folder(folder_path, options)?;
+    if options.git_info {
+        add_git_info(folder_path, "HEAD", true, &mut info, &mut records)?;
+    }
+    Ok(Collected {
+        records,
+        dirs,
+        info,
+    })
+}
+
+/// Reads the files under `folder_path` that the options select, skipping
+/// hidden and ignored ones, and lists the directories walked.
+fn walk_folder(folder_path: &str, options: &Options) -> Result<(Vec<FileRecord>, Vec<String>)> {
+    let mut records = Vec::new();
+    let mut dirs = Vec::new();
     let walker = WalkBuilder::new(folder_path).build();
     for result in walker {
         let entry = result?;
         let path = entry.path();
-        if path.is_file() && should_process_file(path, include, exclude) {
-            let data = process_file(path).context("Failed to process file")?;
+        if entry.depth() > 0 && entry.file_type().is_some_and(|kind| kind.is_dir()) {
+            dirs.push(relative_path(path, Path::new(folder_path)));
+        }
+        if !path.is_file() {
+            continue;
+        }
+        let rel_path = relative_path(path, Path::new(folder_path));
+        if options.selects(&rel_path) {
+            let Some(mut record) =
+                process_file(path, Path::new(folder_path)).context("Failed to process file")?
+            else {
+                continue;
+            };
+            record.diff = options.diff(&rel_path);
             println!("{}", path.to_str().unwrap());
-            writeln!(output, "{}", data)?;
+            records.push(record);
         }
     }
+    Ok((records, dirs))
+}
+
+/// Concatenates exactly the files in `list`, relative to `folder_path`
+/// unless absolute, without walking the folder or applying any patterns.
+/// Listed paths that are not files are skipped with a note.
+fn process_file_list(
+    folder_path: &str,
+    list: &[String],
+    mut info: RunInfo,
+    options: &Options,
+) -> Result<Collected> {
+    let root = Path::new(folder_path);
+    let mut records = Vec::new();
+    for entry in list {
+        let path = root.join(entry);
+        if !path.is_file() {
+            println!("Skipping {}: not a file", entry);
+            continue;
+        }
+        let Some(mut record) = process_file(&path, root).context("Failed to process file")? else {
+            continue;
+        };
+        if !path.starts_with(root) {
+            // Files outside the folder keep the path they were listed as,
+            // made relative so the tree has no `/` node.
+            record.rel_path = entry.replace('\\', "/").trim_start_matches('/').to_string();
+        }
+        println!("{}", path.display());
+        records.push(record);
+    }
+    if options.git_info {
+        add_git_info(folder_path, "HEAD", true, &mut info, &mut records)?;
+    }
+    Ok(Collected {
+        records,
+        dirs: Vec::new(),
+        info,
+    })
+}
+
+/// Puts a `Git` section first in `info` and fills in each record's last
+/// commit as of `rev`. Inputs outside of git get a note instead.
+fn add_git_info(
+    folder_path: &str,
+    rev: &str,
+    working_tree: bool,
+    info: &mut RunInfo,
+    records: &mut [FileRecord],
+) -> Result<()> {
+    let remote_url = match input::classify(&info.repo) {
+        Input::Remote(url) => Some(url),
+        Input::Local(_) | Input::Archive(_) => None,
+    };
+    let body = match gitinfo::describe(folder_path, rev, working_tree, remote_url.as_deref()) {
+        Ok(body) => body,
+        Err(error) => {
+            println!("No git metadata for '{}': {:#}", info.repo, error);
+            return Ok(());
+        }
+    };
+    info.context.insert(0, Section::new("Git", body));
+
+    let paths: Vec<&str> = records
+        .iter()
+        .map(|record| record.rel_path.as_str())
+        .collect();
+    let mut last_commits = gitinfo::last_commits(folder_path, rev, &paths)?;
+    for record in records {
+        record.last_commit = last_commits.remove(&record.rel_path);
+    }
+    Ok(())
+}
+
+/// Commit checked out at `dir`, if it is inside a git repository.
+fn head_commit(dir: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Writes the selected files, `dirs` being every directory seen while
+/// collecting them (used to mark excluded directories in the tree).
+fn write_output(
+    records: Vec<FileRecord>,
+    dirs: &[String],
+    info: &RunInfo,
+    options: &Options,
+) -> Result<()> {
+    let uses_tokens = options
+        .template
+        .as_ref()
+        .is_some_and(|template| template.uses("tokens"));
+    let counter = if options.stats
+        || uses_tokens
+        || options.max_tokens.is_some()
+        || matches!(options.split, Some(SplitLimit::Tokens(_)))
+        || options.tree == Some(TreeAnnotation::Tokens)
+    {
+        Some(TokenCounter::new(options.tokenizer)?)
+    } else {
+        None
+    };
+    let count = |text: &str| counter.as_ref().map_or(0, |counter| counter.count(text));
+    let renderer = Renderer {
+        format: options.format,
+        template: options.template.as_ref(),
+        counter: counter.as_ref(),
+        info,
+    };
+
+    let mut preamble = String::new();
+    if let Some(reference) = &info.reference {
+        let source = format!(
+            "Repository: {}\nRef: {}\nCommit: {}\n",
+            info.repo,
+            reference,
+            info.commit.as_deref().unwrap_or("unknown")
+        );
+        match renderer.preamble(&Section::new("Source", source.clone())) {
+            Some(text) => preamble.push_str(&text),
+            None => println!(
+                "Resolved {} to commit {}",
+                reference,
+                info.commit.as_deref().unwrap_or("unknown")
+            ),
+        }
+    }
+    for section in &info.context {
+        match renderer.preamble(section) {
+            Some(text) => preamble.push_str(&text),
+            None => println!(
+                "{:?} output has no room for the {} section",
+                options.format, section.title
+            ),
+        }
+    }
+    let tree = |records: &[FileRecord], annotation: TreeAnnotation| {
+        let files = records
+            .iter()
+            .map(|record| {
+                let weight = match annotation {
+                    TreeAnnotation::Plain => 0,
+                    TreeAnnotation::Size => record.size(),
+                    TreeAnnotation::Tokens => count(&record.contents),
+                };
+                (record.rel_path.as_str(), weight)
+            })
+            .collect::<Vec<_>>();
+        tree::render(&files, dirs, annotation)
+    };
+
+    let records = match (options.max_tokens, &counter) {
+        (Some(max_tokens), Some(counter)) => {
+            // The tree only shows the files that make it in, so room is kept
+            // for a tree of every file, which is never smaller.
+            let tree_tokens = options.tree.map_or(0, |annotation| {
+                renderer
+                    .preamble(&Section::new("Directory tree", tree(&records, annotation)))
+                    .map_or(0, |text| count(&text))
+            });
+            let fixed = count(&preamble) + tree_tokens;
+            if fixed > max_tokens {
+                bail!(
+                    "The sections before the files take {} tokens, more than --max-tokens {}{}",
+                    fixed,
+                    max_tokens,
+                    if tree_tokens > 0 {
+                        format!(" ({} of them for --tree)", tree_tokens)
+                    } else {
+                        String::new()
+                    }
+                );
+            }
+            budget::select(
+                records,
+                max_tokens - fixed,
+                &options.priority,
+                &renderer,
+                counter,
+            )?
+        }
+        _ => records,
+    };
+    if let Some(annotation) = options.tree {
+        let tree = tree(&records, annotation);
+        match renderer.preamble(&Section::new("Directory tree", tree.clone())) {
+            Some(text) => preamble.push_str(&text),
+            None => println!(
+                "{:?} output has no room for a tree:\n{}",
+                options.format, tree
+            ),
+        }
+    }
+    let chunks = match options.split {
+        Some(SplitLimit::Tokens(limit)) => {
+            split::plan_chunks(records, limit, count(&preamble), &renderer, &count)?
+        }
+        Some(SplitLimit::Bytes(limit)) => {
+            split::plan_chunks(records, limit, preamble.len(), &renderer, &str::len)?
+        }
+        None => vec![records],
+    };
+
+    let mut file_tokens = Vec::new();
+    let mut overhead = 0;
+    let mut tally = |text: &str, path: Option<&str>| {
+        if options.stats {
+            let tokens = count(text);
+            match path {
+                Some(path) => file_tokens.push((path.to_string(), tokens)),
+                None => overhead += tokens,
+            }
+        }
+    };
+
+    let mut chunk_index = Vec::new();
+    let mut index = 0;
+    for (number, chunk) in chunks.iter().enumerate() {
+        let output_path = match options.split {
+            Some(_) => split::chunk_path(&options.output, number + 1),
+            None => options.output.clone(),
+        };
+        let mut output = File::create(&output_path).context("Failed to create output file")?;
+        let mut emit = |text: &str, path: Option<&str>| -> Result<()> {
+            tally(text, path);
+            write!(output, "{}", text)?;
+            Ok(())
+        };
+
+        emit(&renderer.header(), None)?;
+        if number == 0 {
+            emit(&preamble, None)?;
+        }
+        for (position, record) in chunk.iter().enumerate() {
+            if position > 0 {
+                emit(&renderer.separator(), None)?;
+            }
+            emit(
+                &renderer.render_file(index, record)?,
+                Some(&record.rel_path),
+            )?;
+            index += 1;
+            let entry = (record.rel_path.clone(), output_path.clone());
+            if chunk_index.last() != Some(&entry) {
+                chunk_index.push(entry);
+            }
+        }
+        emit(&renderer.footer(), None)?;
+    }
+
+    if options.split.is_some() {
+        let index_path = split::index_path(&options.output);
+        let mut index_file = File::create(&index_path).context("Failed to create index file")?;
+        for (path, chunk) in &chunk_index {
+            writeln!(index_file, "{}\t{}", path, chunk)?;
+        }
+        println!(
+            "Split output into {} chunks, indexed in '{}'",
+            chunks.len(),
+            index_path
+        );
+    }
+
+    if options.stats {
+        let files: Vec<(&str, usize)> = file_tokens
+            .iter()
+            .map(|(path, tokens)| (path.as_str(), *tokens))
+            .collect();
+        tokens::print_report(&files, overhead);
+    }
     Ok(())
 }
@@ -0,0 +1,249 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::FileRecord;
+
+/// First line of every pack, identifying the framing version.
+pub const MAGIC: &str = "repocat-pack v1";
+
+const BEGIN: &str = "<<<repocat-file ";
+const END: &str = "<<<repocat-end ";
+
+/// A file stored in a pack.
+pub struct PackEntry {
+    pub path: String,
+    pub contents: String,
+}
+
+/// Frames a file losslessly:
+///
+/// ```text
+/// <<<repocat-file <boundary> "<json-escaped path>"
+/// <contents, byte for byte>
+/// <<<repocat-end <boundary>
+/// ```
+///
+/// The boundary is derived from the file's hash and never occurs in its
+/// contents, so a section ends at the first matching end marker. Exactly one
+/// newline separates the contents from the end marker.
+pub fn render_file(record: &FileRecord) -> Result<String> {
+    let boundary = boundary_for(&record.contents, &record.sha256());
+    Ok(format!(
+        "{BEGIN}{boundary} {}\n{}\n{END}{boundary}\n",
+        serde_json::to_string(&record.rel_path)?,
+        record.contents
+    ))
+}
+
+fn boundary_for(contents: &str, sha256: &str) -> String {
+    let base = &sha256[..16];
+    let mut boundary = base.to_string();
+    let mut attempt = 0;
+    while contents.contains(&boundary) {
+        attempt += 1;
+        boundary = format!("{}-{}", base, attempt);
+    }
+    boundary
+}
+
+/// Parses every file section in `data`. Text outside of sections, such as the
+/// magic line or commentary added by a model, is ignored.
+pub fn parse(data: &str) -> Result<Vec<PackEntry>> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while let Some(begin) = find_line_start(data, pos, BEGIN) {
+        let line_end = data[begin..]
+            .find('\n')
+            .map(|i| begin + i)
+            .ok_or_else(|| anyhow!("Truncated file header at byte {}", begin))?;
+        let header = &data[begin + BEGIN.len()..line_end];
+        let (boundary, path) = header
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Malformed file header: {}", header))?;
+        let path: String = serde_json::from_str(path)
+            .with_context(|| format!("Malformed path in file header: {}", header))?;
+
+        let content_start = line_end + 1;
+        let end_marker = format!("\n{END}{boundary}");
+        let content_end = data[line_end..]
+            .match_indices(&end_marker)
+            .map(|(i, _)| line_end + i)
+            .find(|&i| {
+                let after = i + end_marker.len();
+                after == data.len() || data[after..].starts_with('\n')
+            })
+            .ok_or_else(|| anyhow!("Missing end marker for '{}'", path))?;
+
+        // Tolerate an empty file whose separating newline was dropped, leaving
+        // the end marker directly after the header line.
+        let contents = if content_end < content_start {
+            String::new()
+        } else {
+            data[content_start..content_end].to_string()
+        };
+        entries.push(PackEntry { path, contents });
+        pos = content_end + end_marker.len();
+    }
+    Ok(entries)
+}
+
+fn find_line_start(data: &str, from: usize, needle: &str) -> Option<usize> {
+    data[from..]
+        .match_indices(needle)
+        .map(|(i, _)| from + i)
+        .find(|&i| i == 0 || data.as_bytes()[i - 1] == b'\n')
+}
+
+/// Validates a path taken from untrusted input and returns it as a relative
+/// path that cannot escape the directory it is joined onto.
+pub fn sanitize_path(path: &str) -> Result<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => sanitized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                bail!("Refusing unsafe path '{}'", path)
+            }
+        }
+    }
+    if sanitized.as_os_str().is_empty() {
+        bail!("Refusing empty path '{}'", path);
+    }
+    Ok(sanitized)
+}
+
+/// Joins an untrusted relative path onto `root`, refusing paths that escape it
+/// either lexically or through an existing symlink.
+pub fn safe_join(root: &Path, path: &str) -> Result<PathBuf> {
+    let relative = sanitize_path(path)?;
+    let mut target = root.to_path_buf();
+    for component in relative.components() {
+        target.push(component);
+        if target.is_symlink() {
+            bail!("Refusing to write through symlink '{}'", target.display());
+        }
+    }
+    Ok(target)
+}
+
+/// Recreates the files of a pack under `dir`.
+pub fn unpack(pack_file: &str, dir: &str) -> Result<()> {
+    let data = fs::read_to_string(pack_file)
+        .with_context(|| format!("Failed to read pack '{}'", pack_file))?;
+    let entries = parse(&data)?;
+    if entries.is_empty() {
+        bail!("No files found in '{}'", pack_file);
+    }
+
+    let root = Path::new(dir);
+    for entry in &entries {
+        let target = safe_join(root, &entry.path)?;
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create '{}'", parent.display()))?;
+        }
+        fs::write(&target, &entry.contents)
+            .with_context(|| format!("Failed to write '{}'", target.display()))?;
+        println!("{}", target.display());
+    }
+    println!("Unpacked {} files into '{}'", entries.len(), dir);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(rel_path: &str, contents: &str) -> FileRecord {
+        FileRecord {
+            path: PathBuf::from(rel_path),
+            rel_path: rel_path.to_string(),
+            contents: contents.to_string(),
+            truncated: false,
+            diff: None,
+            last_commit: None,
+        }
+    }
+
+    fn round_trip(files: &[(&str, &str)]) -> Vec<(String, String)> {
+        let mut pack = format!("{}\n", MAGIC);
+        for (rel_path, contents) in files {
+            pack.push_str(&render_file(&record(rel_path, contents)).unwrap());
+        }
+        parse(&pack)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.path, entry.contents))
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_contents_byte_for_byte() {
+        let files = [
+            ("src/main.rs", "fn main() {}\n"),
+            ("empty.txt", ""),
+            ("no-newline.txt", "last line"),
+            ("blank-lines.txt", "\n\n\n"),
+            (
+                "dir/with \"quotes\" and spaces.md",
+                "  indented\r\n\ttabs\n",
+            ),
+            ("markers.txt", "<<<repocat-file x \"y\"\n<<<repocat-end x\n"),
+        ];
+        let expected: Vec<(String, String)> = files
+            .iter()
+            .map(|(path, contents)| (path.to_string(), contents.to_string()))
+            .collect();
+        assert_eq!(round_trip(&files), expected);
+    }
+
+    #[test]
+    fn boundary_avoids_the_contents() {
+        let sha = "0123456789abcdef0123456789abcdef";
+        assert_eq!(boundary_for("plain", sha), "0123456789abcdef");
+        let colliding = "0123456789abcdef and 0123456789abcdef-1";
+        assert_eq!(boundary_for(colliding, sha), "0123456789abcdef-2");
+    }
+
+    #[test]
+    fn ignores_text_around_sections() {
+        let section = render_file(&record("a.txt", "a\n")).unwrap();
+        let data = format!("Here is the change:\n{}\nHope that helps!\n", section);
+        let entries = parse(&data).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].contents, "a\n");
+    }
+
+    #[test]
+    fn reports_a_missing_end_marker() {
+        let section = render_file(&record("a.txt", "a")).unwrap();
+        let truncated = &section[..section.rfind(END).unwrap()];
+        assert!(parse(truncated).is_err());
+    }
+
+    #[test]
+    fn sanitize_path_refuses_escapes() {
+        for path in ["../etc/passwd", "/etc/passwd", "src/../lib.rs", "", "."] {
+            assert!(sanitize_path(path).is_err(), "{} should be refused", path);
+        }
+        assert_eq!(
+            sanitize_path("./src/lib.rs").unwrap(),
+            Path::new("src/lib.rs")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn safe_join_refuses_symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink("/tmp", root.path().join("link")).unwrap();
+        assert!(safe_join(root.path(), "link/file.txt").is_err());
+        assert!(safe_join(root.path(), "../file.txt").is_err());
+        assert_eq!(
+            safe_join(root.path(), "dir/file.txt").unwrap(),
+            root.path().join("dir/file.txt")
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::process::Command;
+
+use crate::changes::{self, ChangeSet, Range};
+use crate::remote::{git, git_output};
+use crate::Section;
+
+/// File names too common to tell which module a file is; the directory
+/// name is searched for instead.
+const GENERIC_STEMS: &[&str] = &["mod", "lib", "main", "index", "__init__", "init"];
+
+/// What to put in a review bundle.
+pub struct Request {
+    pub range: Range,
+    /// Also include files that mention the changed ones
+    pub related: bool,
+    /// Show each file's own diff after it, besides the combined one
+    pub with_diff: bool,
+}
+
+/// Everything a reviewer needs besides the changed files themselves.
+pub struct Review {
+    /// Sections written before the files
+    pub sections: Vec<Section>,
+    /// Changed files, plus the ones referencing them with `related`
+    pub changes: ChangeSet,
+}
+
+/// Gathers the commits, changed files and combined diff of the requested
+/// range in the repository at `folder`, limited to that folder. With
+/// `related`, files at the head revision that mention a changed module by
+/// name are added to the change set too.
+pub fn prepare(folder: &str, request: &Request) -> Result<Review> {
+    let dir = Path::new(folder);
+    let range = &request.range;
+    let base = if range.merge_base {
+        changes::merge_base(folder, &range.base, &range.head)?
+    } else {
+        range.base.clone()
+    };
+    let head = range.head.as_str();
+    let mut changes = ChangeSet::collect(folder, &base, Some(head), request.with_diff)?;
+
+    let commits = git(
+        dir,
+        &[
+            "log",
+            "--reverse",
+            "--date=short",
+            "--format=%h %ad %an%n%w(0,4,4)%B",
+            &format!("{}..{}", base, head),
+            "--",
+            ".",
+        ],
+    )?;
+    let status = git(
+        dir,
+        &[
+            "diff",
+            "--relative",
+            "--no-renames",
+            "--name-status",
+            &base,
+            head,
+        ],
+    )?;
+    let diff = git_output(
+        dir,
+        &[
+            "diff",
+            "--relative",
+            "--no-renames",
+            "--no-ext-diff",
+            &base,
+            head,
+        ],
+    )?;
+
+    let mut sections = vec![
+        Section::new("Commits", commits),
+        Section::new("Changed files", status.clone()),
+        Section::new("Diff", diff),
+    ];
+    if request.related {
+        let names: BTreeSet<&str> = status
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter_map(|(_, path)| module_name(path))
+            .collect();
+        let referencing: Vec<String> = referencing_files(dir, head, &names)?
+            .into_iter()
+            .filter(|path| !changes.contains(path))
+            .collect();
+        for path in &referencing {
+            changes.insert(path);
+        }
+        sections.push(Section::new("Related files", referencing.join("\n")));
+    }
+    // End every section with a blank line like the `Source` one.
+    for section in &mut sections {
+        if !section.body.ends_with('\n') {
+            section.body.push('\n');
+        }
+    }
+    Ok(Review { sections, changes })
+}
+
+/// Name other files would use to import or mention `path`: its file stem,
+/// or its directory for files like `mod.rs` and `__init__.py`.
+fn module_name(path: &str) -> Option<&str> {
+    let mut parts = path.rsplit('/');
+    let file = parts.next()?;
+    let stem = file.split('.').next().unwrap_or(file);
+    let name = if GENERIC_STEMS.contains(&stem) {
+        parts.next()?
+    } else {
+        stem
+    };
+    // Very short names match far too much to be useful.
+    Some(name).filter(|name| name.len() > 2)
+}
+
+/// Files under `dir` at `head` that contain any of `names` as a word.
+fn referencing_files(dir: &Path, head: &str, names: &BTreeSet<&str>) -> Result<Vec<String>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).args(["grep", "-l", "-w", "-F"]);
+    for name in names {
+        command.args(["-e", name]);
+    }
+    let output = command
+        .args([head, "--", "."])
+        .output()
+        .context("Failed to run git")?;
+    // `git grep` exits with 1 when nothing matches.
+    if !output.status.success() && output.status.code() != Some(1) {
+        bail!(
+            "git grep failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let prefix = format!("{}:", head);
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.strip_prefix(&prefix).unwrap_or(line).to_string())
+        .collect())
+}
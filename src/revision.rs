@@ -0,0 +1,222 @@
+use anyhow::Result;
+
+use crate::{FileRecord, Options};
+
+/// Files and directories of a local repository as of some revision.
+pub struct Snapshot {
+    pub records: Vec<FileRecord>,
+    pub dirs: Vec<String>,
+    /// Full id of the commit `rev` resolved to
+    pub commit: String,
+}
+
+/// Reads the files under `folder` as they were at `rev`, straight from the
+/// repository's object database, without touching the working tree.
+///
+/// Mirrors the folder walker: hidden entries, symlinks and submodules are
+/// skipped, and `.gitignore` files are honored as they existed at `rev`.
+#[cfg(feature = "git")]
+pub fn snapshot(folder: &str, rev: &str, options: &Options) -> Result<Snapshot> {
+    use anyhow::Context;
+    use std::path::Path;
+
+    let repo = git2::Repository::discover(folder)
+        .with_context(|| format!("'{}' is not inside a git repository", folder))?;
+    let commit = repo
+        .revparse_single(rev)
+        .with_context(|| format!("Unknown revision '{}'", rev))?
+        .peel_to_commit()
+        .with_context(|| format!("'{}' does not point to a commit", rev))?;
+
+    let base = repo
+        .workdir()
+        .unwrap_or_else(|| repo.path())
+        .canonicalize()?;
+    let prefix = Path::new(folder)
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve '{}'", folder))?
+        .strip_prefix(&base)
+        .map(|prefix| crate::relative_path(prefix, Path::new("")))
+        .unwrap_or_default();
+
+    let mut walk = TreeWalk {
+        repo: &repo,
+        base: &base,
+        folder: Path::new(folder),
+        prefix: &prefix,
+        options,
+        ignores: Vec::new(),
+        snapshot: Snapshot {
+            records: Vec::new(),
+            dirs: Vec::new(),
+            commit: commit.id().to_string(),
+        },
+    };
+    walk.visit(&commit.tree()?, "")?;
+    Ok(walk.snapshot)
+}
+
+/// Same as the `git2` version, checking `rev` out into a temporary
+/// worktree and reading it with the folder walker.
+#[cfg(not(feature = "git"))]
+pub fn snapshot(folder: &str, rev: &str, options: &Options) -> Result<Snapshot> {
+    use anyhow::Context;
+    use std::path::Path;
+
+    use crate::remote::git;
+
+    let dir = Path::new(folder);
+    git(dir, &["rev-parse", "--show-toplevel"])
+        .with_context(|| format!("'{}' is not inside a git repository", folder))?;
+    let commit = git(
+        dir,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)],
+    )
+    .with_context(|| format!("Unknown revision '{}'", rev))?;
+    let prefix = git(dir, &["rev-parse", "--show-prefix"])?;
+
+    let checkout = tempfile::tempdir()?;
+    let checkout_arg = checkout.path().to_string_lossy();
+    git(
+        dir,
+        &[
+            "worktree",
+            "add",
+            "--detach",
+            "--quiet",
+            &checkout_arg,
+            &commit,
+        ],
+    )?;
+    let walked = crate::walk_folder(&checkout.path().join(&prefix).to_string_lossy(), options).map(
+        |(mut records, mut dirs)| {
+            // Symlinks are skipped, and files listed in tree order, as the
+            // `git2` version does.
+            records.retain(|record| !record.path.is_symlink());
+            records.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+            dirs.sort();
+            (records, dirs)
+        },
+    );
+    git(dir, &["worktree", "remove", "--force", &checkout_arg])?;
+    let (mut records, dirs) = walked?;
+    for record in &mut records {
+        record.path = dir.join(&record.rel_path);
+    }
+    Ok(Snapshot {
+        records,
+        dirs,
+        commit,
+    })
+}
+
+#[cfg(feature = "git")]
+struct TreeWalk<'a> {
+    repo: &'a git2::Repository,
+    /// Working directory (or git dir of a bare repository)
+    base: &'a std::path::Path,
+    /// Folder as given on the command line
+    folder: &'a std::path::Path,
+    /// `folder` relative to `base`, `/`-separated; empty for the whole repo
+    prefix: &'a str,
+    options: &'a Options,
+    /// `.gitignore` rules of the directories being visited, outermost first
+    ignores: Vec<ignore::gitignore::Gitignore>,
+    snapshot: Snapshot,
+}
+
+#[cfg(feature = "git")]
+impl TreeWalk<'_> {
+    fn visit(&mut self, tree: &git2::Tree, dir: &str) -> Result<()> {
+        use git2::ObjectType;
+        use ignore::gitignore::GitignoreBuilder;
+        use ignore::Match;
+
+        let pushed = match tree.get_name(".gitignore") {
+            Some(entry) if entry.kind() == Some(ObjectType::Blob) => {
+                let blob = self.repo.find_blob(entry.id())?;
+                let mut builder = GitignoreBuilder::new(self.base.join(dir));
+                for line in String::from_utf8_lossy(blob.content()).lines() {
+                    // Invalid lines are skipped, just like git does.
+                    let _ = builder.add_line(None, line);
+                }
+                self.ignores.push(builder.build()?);
+                true
+            }
+            _ => false,
+        };
+
+        for entry in tree.iter() {
+            let Some(name) = entry.name() else {
+                continue;
+            };
+            if name.starts_with('.') {
+                continue;
+            }
+            let rel = if dir.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            let is_dir = entry.kind() == Some(ObjectType::Tree);
+            let ignored = self
+                .ignores
+                .iter()
+                .rev()
+                .map(|gitignore| gitignore.matched(self.base.join(&rel), is_dir))
+                .find(|matched| !matched.is_none());
+            if matches!(ignored, Some(Match::Ignore(_))) {
+                continue;
+            }
+
+            let in_folder = self.prefix.is_empty() || rel.starts_with(&format!("{}/", self.prefix));
+            let rel_to_folder = if self.prefix.is_empty() {
+                rel.as_str()
+            } else {
+                rel.strip_prefix(&format!("{}/", self.prefix))
+                    .unwrap_or(&rel)
+            };
+
+            if is_dir {
+                let leads_to_folder =
+                    rel == self.prefix || self.prefix.starts_with(&format!("{}/", rel));
+                if in_folder {
+                    self.snapshot.dirs.push(rel_to_folder.to_string());
+                }
+                if in_folder || leads_to_folder {
+                    let subtree = self.repo.find_tree(entry.id())?;
+                    self.visit(&subtree, &rel)?;
+                }
+                continue;
+            }
+
+            // Symlinks are stored as blobs too; skip them like the walker does.
+            if !in_folder || entry.kind() != Some(ObjectType::Blob) || entry.filemode() == 0o120000
+            {
+                continue;
+            }
+            let path = self.folder.join(rel_to_folder);
+            if !self.options.selects(rel_to_folder) {
+                continue;
+            }
+            let blob = self.repo.find_blob(entry.id())?;
+            let Some(contents) = crate::text(blob.content().to_vec(), rel_to_folder) else {
+                continue;
+            };
+            println!("{}", path.display());
+            self.snapshot.records.push(FileRecord {
+                path,
+                rel_path: rel_to_folder.to_string(),
+                contents,
+                truncated: false,
+                diff: self.options.diff(rel_to_folder),
+                last_commit: None,
+            });
+        }
+
+        if pushed {
+            self.ignores.pop();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::format::Renderer;
+use crate::FileRecord;
+
+/// Maximum size of each chunk when splitting the output.
+#[derive(Clone, Copy, Debug)]
+pub enum SplitLimit {
+    Tokens(usize),
+    Bytes(usize),
+}
+
+/// Groups records into chunks whose rendered size, as reported by `measure`,
+/// stays within `limit`. The first chunk also carries `reserved` units of
+/// output that precede the files.
+///
+/// Files are never split unless a file alone exceeds the limit; such files are
+/// cut at line boundaries with a marker at each cut. Packs must stay lossless,
+/// so with `--format pack` an oversized file gets a chunk of its own instead.
+pub fn plan_chunks(
+    records: Vec<FileRecord>,
+    limit: usize,
+    reserved: usize,
+    renderer: &Renderer,
+    measure: &dyn Fn(&str) -> usize,
+) -> Result<Vec<Vec<FileRecord>>> {
+    let frame = measure(&renderer.header()) + measure(&renderer.footer());
+    let separator = measure(&renderer.separator());
+
+    let mut chunks: Vec<Vec<FileRecord>> = Vec::new();
+    let mut current: Vec<FileRecord> = Vec::new();
+    let mut used = frame + reserved;
+    let mut index = 0;
+
+    for record in records {
+        let cost = measure(&renderer.render_file(index, &record)?);
+        let pieces = if frame + cost > limit && !renderer.is_lossless() {
+            println!("Splitting {} across chunks", record.rel_path);
+            split_record(
+                &record,
+                limit.saturating_sub(frame),
+                index,
+                renderer,
+                measure,
+            )?
+        } else {
+            vec![(record, cost)]
+        };
+
+        for (piece, cost) in pieces {
+            if !current.is_empty() && used + separator + cost > limit {
+                chunks.push(std::mem::take(&mut current));
+                used = frame;
+            }
+            if !current.is_empty() {
+                used += separator;
+            } else if frame + cost > limit {
+                println!("{} alone exceeds the chunk limit", piece.rel_path);
+            }
+            used += cost;
+            current.push(piece);
+            index += 1;
+        }
+    }
+    if !current.is_empty() || chunks.is_empty() {
+        chunks.push(current);
+    }
+    Ok(chunks)
+}
+
+/// Cuts an oversized record into consecutive pieces that each fit in `room`.
+fn split_record(
+    record: &FileRecord,
+    room: usize,
+    index: usize,
+    renderer: &Renderer,
+    measure: &dyn Fn(&str) -> usize,
+) -> Result<Vec<(FileRecord, usize)>> {
+    let lines: Vec<&str> = record.contents.split_inclusive('\n').collect();
+    if lines.is_empty() {
+        // Nothing to cut: an empty file is oversized by its markup alone.
+        let piece = piece(record, &lines, 0, 0);
+        let cost = measure(&renderer.render_file(index, &piece)?);
+        return Ok(vec![(piece, cost)]);
+    }
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        // Binary search for the most lines that still fit; always take at
+        // least one so a single huge line cannot stall the loop.
+        let (mut low, mut high) = (1, lines.len() - start);
+        let mut best = None;
+        while low <= high {
+            let take = (low + high) / 2;
+            let piece = piece(record, &lines, start, take);
+            let cost = measure(&renderer.render_file(index + pieces.len(), &piece)?);
+            if cost <= room || take == 1 {
+                best = Some((piece, cost, take));
+                low = take + 1;
+            } else {
+                high = take - 1;
+            }
+        }
+        let (piece, cost, take) = best.expect("at least one line is always taken");
+        pieces.push((piece, cost));
+        start += take;
+    }
+    Ok(pieces)
+}
+
+fn piece(record: &FileRecord, lines: &[&str], start: usize, take: usize) -> FileRecord {
+    let last = start + take == lines.len();
+    let mut contents = String::new();
+    if start > 0 {
+        contents.push_str("... [continued from the previous chunk] ...\n");
+    }
+    contents.extend(lines[start..start + take].iter().copied());
+    if !last {
+        if !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str("... [continued in the next chunk] ...\n");
+    }
+    FileRecord {
+        path: record.path.clone(),
+        rel_path: record.rel_path.clone(),
+        contents,
+        truncated: record.truncated,
+        // The diff follows the end of the file.
+        diff: record.diff.clone().filter(|_| last),
+        last_commit: record.last_commit.clone(),
+    }
+}
+
+/// `out.txt` becomes `out.001.txt`, `out.002.txt`, ...
+pub fn chunk_path(output: &str, number: usize) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{}.{:03}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}.{:03}", stem, number),
+    };
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// `out.txt` becomes `out.index.tsv`.
+pub fn index_path(output: &str) -> String {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.index.tsv", stem))
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+
+/// Placeholders available in every section.
+const RUN_PLACEHOLDERS: &[&str] = &["repo", "commit", "ref"];
+
+/// Placeholders only available in the `file` section.
+const FILE_PLACEHOLDERS: &[&str] = &[
+    "path",
+    "lang",
+    "lines",
+    "size",
+    "tokens",
+    "content",
+    "sha",
+    "index",
+    "diff",
+    "last_commit",
+    "last_author",
+    "last_date",
+];
+
+/// A user-supplied output layout made of up to four sections:
+///
+/// ```text
+/// {{#header}}Repository {{repo}} at {{commit}}
+/// {{/header}}
+/// {{#file}}
+/// === {{path}} ({{lang}}, {{lines}} lines) ===
+/// {{content}}
+/// {{/file}}
+/// {{#separator}}
+/// {{/separator}}
+/// {{#footer}}{{/footer}}
+/// ```
+///
+/// A newline directly after an opening tag is not part of the section. Only
+/// `file` is required; a template without any sections is used as `file`.
+pub struct Template {
+    pub header: String,
+    pub file: String,
+    pub separator: String,
+    pub footer: String,
+}
+
+impl Template {
+    pub fn load(path: &str) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template '{}'", path))?;
+        Template::parse(&text).with_context(|| format!("Invalid template '{}'", path))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let template = if text.contains("{{#") {
+            Template {
+                header: section(text, "header")?.unwrap_or_default(),
+                file: section(text, "file")?
+                    .ok_or_else(|| anyhow!("Missing {{{{#file}}}} section"))?,
+                separator: section(text, "separator")?.unwrap_or_default(),
+                footer: section(text, "footer")?.unwrap_or_default(),
+            }
+        } else {
+            Template {
+                header: String::new(),
+                file: text.to_string(),
+                separator: String::new(),
+                footer: String::new(),
+            }
+        };
+
+        for (name, body) in [
+            ("header", &template.header),
+            ("separator", &template.separator),
+            ("footer", &template.footer),
+        ] {
+            for placeholder in placeholders(body) {
+                if !RUN_PLACEHOLDERS.contains(&placeholder) {
+                    bail!(
+                        "Unknown placeholder {{{{{}}}}} in {} section",
+                        placeholder,
+                        name
+                    );
+                }
+            }
+        }
+        for placeholder in placeholders(&template.file) {
+            if !RUN_PLACEHOLDERS.contains(&placeholder) && !FILE_PLACEHOLDERS.contains(&placeholder)
+            {
+                bail!(
+                    "Unknown placeholder {{{{{}}}}} in file section",
+                    placeholder
+                );
+            }
+        }
+        Ok(template)
+    }
+
+    /// Whether any section refers to `{{name}}`.
+    pub fn uses(&self, name: &str) -> bool {
+        [&self.header, &self.file, &self.separator, &self.footer]
+            .iter()
+            .any(|body| placeholders(body).any(|placeholder| placeholder == name))
+    }
+}
+
+fn section(text: &str, name: &str) -> Result<Option<String>> {
+    let open = format!("{{{{#{}}}}}", name);
+    let close = format!("{{{{/{}}}}}", name);
+    let Some(start) = text.find(&open) else {
+        return Ok(None);
+    };
+    let body_start = start + open.len();
+    let Some(end) = text[body_start..].find(&close) else {
+        bail!("Missing {} for {}", close, open);
+    };
+    let body = &text[body_start..body_start + end];
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))
+        .unwrap_or(body);
+    Ok(Some(body.to_string()))
+}
+
+fn placeholders(body: &str) -> impl Iterator<Item = &str> {
+    body.split("{{")
+        .skip(1)
+        .filter_map(|rest| rest.split_once("}}").map(|(name, _)| name.trim()))
+}
+
+/// Replaces every `{{name}}` in `body` with `value(name)`. Substituted values
+/// are never expanded again, so file contents may contain `{{` safely.
+pub fn fill(body: &str, value: &dyn Fn(&str) -> String) -> String {
+    let mut out = String::with_capacity(body.len());
+    let mut rest = body;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&value(rest[start + 2..start + 2 + end].trim()));
+        rest = &rest[start + 2 + end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,75 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use tiktoken_rs::CoreBPE;
+
+/// Tokenizer used to measure the output. All vocabularies are bundled, so
+/// counting never touches the network.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Tokenizer {
+    /// cl100k_base BPE (GPT-4, GPT-3.5)
+    Cl100k,
+    /// o200k_base BPE (GPT-4o and newer)
+    #[default]
+    O200k,
+    /// Cheap estimate of one token per four characters
+    Chars,
+}
+
+/// Counts tokens with a tokenizer whose vocabulary is loaded once up front.
+pub struct TokenCounter {
+    bpe: Option<CoreBPE>,
+}
+
+impl TokenCounter {
+    pub fn new(tokenizer: Tokenizer) -> Result<Self> {
+        let bpe = match tokenizer {
+            Tokenizer::Cl100k => Some(tiktoken_rs::cl100k_base()?),
+            Tokenizer::O200k => Some(tiktoken_rs::o200k_base()?),
+            Tokenizer::Chars => None,
+        };
+        Ok(TokenCounter { bpe })
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match &self.bpe {
+            Some(bpe) => bpe.encode_ordinary(text).len(),
+            None => text.chars().count().div_ceil(4),
+        }
+    }
+}
+
+/// Prints token counts per file, per directory and in total, largest first.
+///
+/// `overhead` covers output that belongs to no file, such as format headers.
+pub fn print_report(files: &[(&str, usize)], overhead: usize) {
+    let mut directories: BTreeMap<String, usize> = BTreeMap::new();
+    for (path, tokens) in files {
+        let mut dir = *path;
+        while let Some((parent, _)) = dir.rsplit_once('/') {
+            *directories.entry(format!("{}/", parent)).or_default() += tokens;
+            dir = parent;
+        }
+    }
+
+    let total: usize = files.iter().map(|(_, tokens)| tokens).sum::<usize>() + overhead;
+    let width = total.to_string().len();
+
+    let mut files = files.to_vec();
+    files.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    println!("\nTokens per file:");
+    for (path, tokens) in &files {
+        println!("  {:>width$}  {}", tokens, path);
+    }
+
+    if !directories.is_empty() {
+        let mut directories: Vec<_> = directories.into_iter().collect();
+        directories.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        println!("\nTokens per directory:");
+        for (dir, tokens) in &directories {
+            println!("  {:>width$}  {}", tokens, dir);
+        }
+    }
+
+    println!("\nTotal: {} tokens in {} files", total, files.len());
+}
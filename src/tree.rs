@@ -0,0 +1,99 @@
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+/// What, if anything, to print next to each entry of the directory tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TreeAnnotation {
+    /// Names only
+    Plain,
+    /// File sizes, summed up for directories
+    Size,
+    /// Token counts, summed up for directories
+    Tokens,
+}
+
+#[derive(Default)]
+struct Node {
+    children: BTreeMap<String, Node>,
+    is_dir: bool,
+    included: bool,
+    weight: usize,
+}
+
+/// Renders an ASCII tree of `files` (relative path and weight, either bytes or
+/// tokens depending on `annotation`). Directories from `dirs` that contain no
+/// included file are listed as excluded.
+pub fn render(files: &[(&str, usize)], dirs: &[String], annotation: TreeAnnotation) -> String {
+    let mut root = Node {
+        is_dir: true,
+        included: true,
+        ..Node::default()
+    };
+    for dir in dirs {
+        let mut node = &mut root;
+        for part in dir.split('/').filter(|part| !part.is_empty()) {
+            node = node.children.entry(part.to_string()).or_default();
+            node.is_dir = true;
+        }
+    }
+    for (path, weight) in files {
+        root.weight += weight;
+        let mut parts = path.split('/').peekable();
+        let mut node = &mut root;
+        while let Some(part) = parts.next() {
+            node = node.children.entry(part.to_string()).or_default();
+            node.included = true;
+            node.weight += weight;
+            node.is_dir |= parts.peek().is_some();
+        }
+    }
+
+    let mut out = format!(".{}\n", label(&root, annotation));
+    render_children(&root, "", annotation, &mut out);
+    out
+}
+
+fn render_children(node: &Node, prefix: &str, annotation: TreeAnnotation, out: &mut String) {
+    let count = node.children.len();
+    for (position, (name, child)) in node.children.iter().enumerate() {
+        let last = position + 1 == count;
+        out.push_str(&format!(
+            "{}{}{}{}{}\n",
+            prefix,
+            if last { "└── " } else { "├── " },
+            name,
+            if child.is_dir { "/" } else { "" },
+            label(child, annotation)
+        ));
+        if child.included {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            render_children(child, &prefix, annotation, out);
+        }
+    }
+}
+
+fn label(node: &Node, annotation: TreeAnnotation) -> String {
+    if !node.included {
+        return " [excluded]".to_string();
+    }
+    match annotation {
+        TreeAnnotation::Plain => String::new(),
+        TreeAnnotation::Size => format!(" ({})", human_size(node.weight)),
+        TreeAnnotation::Tokens => format!(" ({} tokens)", node.weight),
+    }
+}
+
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}